@@ -0,0 +1,389 @@
+// Encodes the PCM music in music/ as IMA ADPCM, which is a quarter the size,
+// so it doesn't dominate the kernel image. For every music/name.wav
+// (16 bit PCM) or music/name.raw (headerless 16 bit stereo at 48kHz)
+// this writes $OUT_DIR/name.adpcm.wav, which src/spacefox/music_data.rs
+// can include_bytes! like any other asset. Everything else in music/ is left alone,
+// including any .wav we can't encode, which gets a warning instead.
+// Each one is also split into name.lows.adpcm.wav and name.highs.adpcm.wav,
+// which add back up to the whole song, for adaptive music (see src/audio/layers.rs).
+// Only what music_data.rs includes ends up in the kernel.
+// It also finds the beats in each of them, for rhythm mode, and writes
+// $OUT_DIR/name.beats.rs, see src/spacefox/rhythm.rs.
+
+use std::{
+    env,
+    f32::consts::{FRAC_1_SQRT_2, PI},
+    fs,
+    path::Path,
+};
+
+// Shared with the decoder, so they can't disagree about the codec
+#[allow(dead_code)]
+#[path = "src/audio/adpcm/ima.rs"]
+mod ima;
+
+use ima::ImaState;
+
+const RAW_SAMPLE_RATE: u32 = 48_000;
+const RAW_CHANNELS: u16 = 2;
+
+// 1 KiB per channel per block, like most encoders do
+const BLOCK_BYTES_PER_CHANNEL: usize = 1024;
+const HEADER_BYTES_PER_CHANNEL: usize = 4;
+const SAMPLES_PER_GROUP: usize = 8;
+const FRAMES_PER_BLOCK: usize =
+    1 + (BLOCK_BYTES_PER_CHANNEL - HEADER_BYTES_PER_CHANNEL) / 4 * SAMPLES_PER_GROUP;
+
+struct Pcm {
+    channels: u16,
+    sample_rate: u32,
+    // Interleaved
+    samples: Vec<i16>,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/audio/adpcm/ima.rs");
+    println!("cargo:rerun-if-changed=music");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let Ok(entries) = fs::read_dir("music") else {
+        return;
+    };
+
+    for entry in entries {
+        let path = entry.unwrap().path();
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let bytes = fs::read(&path).unwrap();
+        let pcm = match path.extension().and_then(|e| e.to_str()) {
+            Some("wav") => match read_wav(&bytes) {
+                Ok(pcm) => pcm,
+                Err(why) => {
+                    println!("cargo:warning={}: {why}, skipping it", path.display());
+                    continue;
+                }
+            },
+            Some("raw") => Pcm {
+                channels: RAW_CHANNELS,
+                sample_rate: RAW_SAMPLE_RATE,
+                samples: read_samples(&bytes),
+            },
+            _ => continue,
+        };
+
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let (lows, highs) = split(&pcm);
+        for (name, pcm) in [("", &pcm), (".lows", &lows), (".highs", &highs)] {
+            let out = Path::new(&out_dir).join(format!("{stem}{name}.adpcm.wav"));
+            fs::write(out, encode(pcm)).unwrap();
+        }
+        let out = Path::new(&out_dir).join(format!("{stem}.beats.rs"));
+        fs::write(out, beat_map_source(&pcm, &path)).unwrap();
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn read_samples(bytes: &[u8]) -> Vec<i16> {
+    let (samples, _) = bytes.as_chunks::<2>();
+    samples.iter().map(|&b| i16::from_le_bytes(b)).collect()
+}
+
+// Just enough of a .wav reader for our own assets,
+// see src/audio/wav.rs for the forgiving one
+fn read_wav(bytes: &[u8]) -> Result<Pcm, &'static str> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a RIFF WAVE file");
+    }
+
+    let mut fmt = None;
+    let mut data = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let len = read_u32(bytes, at + 4) as usize;
+        let body = &bytes[at + 8..(at + 8 + len).min(bytes.len())];
+        match &bytes[at..at + 4] {
+            b"fmt " => fmt = Some(body),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        at += 8 + len + (len & 1);
+    }
+
+    let fmt = fmt.ok_or("no fmt chunk")?;
+    let data = data.ok_or("no data chunk")?;
+    // 8 and 24 bit, float, WAVE_FORMAT_EXTENSIBLE, and already compressed
+    // files all end up here. Re-export them as 16 bit PCM to use them.
+    if fmt.len() < 16 || read_u16(fmt, 0) != 1 || read_u16(fmt, 14) != 16 {
+        return Err("only 16 bit PCM can be encoded");
+    }
+    let channels = read_u16(fmt, 2);
+    if !matches!(channels, 1 | 2) {
+        return Err("only mono or stereo can be encoded");
+    }
+
+    Ok(Pcm {
+        channels,
+        sample_rate: read_u32(fmt, 4),
+        samples: read_samples(data),
+    })
+}
+
+fn encode(pcm: &Pcm) -> Vec<u8> {
+    let channels = pcm.channels as usize;
+    let frames = pcm.samples.len() / channels;
+    let sample = |frame: usize, c: usize| {
+        // Pad a short last group out with the final frame
+        let frame = frame.min(frames.saturating_sub(1));
+        pcm.samples[frame * channels + c]
+    };
+
+    let mut data = Vec::new();
+    let mut state = [ImaState::default(); 2];
+    for block_start in (0..frames).step_by(FRAMES_PER_BLOCK) {
+        let block_frames = (frames - block_start).min(FRAMES_PER_BLOCK);
+
+        // The step index carries over from the last block,
+        // only the predictor is reset to the real sample
+        for (c, state) in state[..channels].iter_mut().enumerate() {
+            state.predictor = sample(block_start, c);
+            data.extend_from_slice(&state.predictor.to_le_bytes());
+            data.push(state.step_index);
+            data.push(0);
+        }
+
+        let groups = (block_frames - 1).div_ceil(SAMPLES_PER_GROUP);
+        for group in 0..groups {
+            let first = block_start + 1 + group * SAMPLES_PER_GROUP;
+            for (c, state) in state[..channels].iter_mut().enumerate() {
+                for pair in 0..SAMPLES_PER_GROUP / 2 {
+                    let low = state.encode(sample(first + pair * 2, c));
+                    let high = state.encode(sample(first + pair * 2 + 1, c));
+                    data.push(low | (high << 4));
+                }
+            }
+        }
+    }
+
+    let block_align = BLOCK_BYTES_PER_CHANNEL * channels;
+    let byte_rate = pcm.sample_rate as usize * block_align / FRAMES_PER_BLOCK;
+
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&0x0011u16.to_le_bytes()); // IMA ADPCM
+    fmt.extend_from_slice(&pcm.channels.to_le_bytes());
+    fmt.extend_from_slice(&pcm.sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(byte_rate as u32).to_le_bytes());
+    fmt.extend_from_slice(&(block_align as u16).to_le_bytes());
+    fmt.extend_from_slice(&4u16.to_le_bytes()); // bits per sample
+    fmt.extend_from_slice(&2u16.to_le_bytes()); // extra fmt bytes
+    fmt.extend_from_slice(&(FRAMES_PER_BLOCK as u16).to_le_bytes());
+
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&0u32.to_le_bytes()); // filled in below
+    wav.extend_from_slice(b"WAVE");
+    for (id, body) in [
+        (b"fmt ", fmt.as_slice()),
+        (b"fact", &(frames as u32).to_le_bytes()[..]),
+        (b"data", data.as_slice()),
+    ] {
+        wav.extend_from_slice(id);
+        wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        wav.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            wav.push(0);
+        }
+    }
+    let riff_len = (wav.len() - 8) as u32;
+    wav[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    wav
+}
+
+// Everything below this goes in the lows, and everything above in the highs.
+// The highs are mostly drums: hi-hats, cymbals, snares, and the click of the kick.
+const CROSSOVER_HZ: f32 = 2000.0;
+
+// The lows go through a Butterworth low pass, from
+// https://www.w3.org/TR/audio-eq-cookbook/, and the highs are whatever it took out,
+// so the two always add back up to the original
+fn split(pcm: &Pcm) -> (Pcm, Pcm) {
+    let channels = pcm.channels as usize;
+    let crossover = CROSSOVER_HZ.min(pcm.sample_rate as f32 / 4.0);
+    let w0 = 2.0 * PI * crossover / pcm.sample_rate as f32;
+    let alpha = w0.sin() * FRAC_1_SQRT_2;
+    let a0 = 1.0 + alpha;
+    let b0 = (1.0 - w0.cos()) / 2.0 / a0;
+    let b1 = (1.0 - w0.cos()) / a0;
+    let a1 = -2.0 * w0.cos() / a0;
+    let a2 = (1.0 - alpha) / a0;
+
+    // The last two inputs and outputs of each channel
+    let mut history = vec![[0.0f32; 4]; channels];
+    let mut lows = Vec::with_capacity(pcm.samples.len());
+    let mut highs = Vec::with_capacity(pcm.samples.len());
+    for frame in pcm.samples.chunks(channels) {
+        for (&sample, history) in frame.iter().zip(history.iter_mut()) {
+            let x = sample as f32;
+            let [x1, x2, y1, y2] = *history;
+            let y = b0 * x + b1 * x1 + b0 * x2 - a1 * y1 - a2 * y2;
+            *history = [x, x1, y, y1];
+
+            let low = y.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            lows.push(low);
+            highs.push((sample as i32 - low as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16);
+        }
+    }
+
+    let band = |samples| Pcm {
+        channels: pcm.channels,
+        sample_rate: pcm.sample_rate,
+        samples,
+    };
+    (band(lows), band(highs))
+}
+
+// Beat detection, which only has to be good enough to put towers on the hits.
+// We look for onsets (sudden jumps in loudness), guess the tempo from how
+// the onsets line up with themselves, then walk through the song a beat at
+// a time, snapping to the nearest onset. Only beats that land on an onset
+// make it into the map, so there's always something to hear.
+// See https://www.music.mcgill.ca/~ich/classes/mumt611_07/Beat/BeatTracking.pdf
+// for far better ways of doing all of this.
+
+const HOPS_PER_SECOND: usize = 100;
+const MIN_BPM: usize = 70;
+const MAX_BPM: usize = 180;
+// An onset has to be this much stronger than the average around it
+const ONSET_THRESHOLD: f32 = 1.5;
+// How far either side of the expected beat we look for an onset, out of 1
+const SNAP_WINDOW: f32 = 0.2;
+// Matches SAMPLE_RATE in src/audio/mod.rs, which positions are counted in
+const OUTPUT_RATE: u64 = 48_000;
+
+struct Onset {
+    hop: usize,
+    strength: f32,
+    // How much of the energy is in the highs, used to pick a lane
+    brightness: f32,
+}
+
+fn beat_map_source(pcm: &Pcm, path: &Path) -> String {
+    let channels = pcm.channels as usize;
+    let hop_len = (pcm.sample_rate as usize / HOPS_PER_SECOND).max(1);
+    let mono: Vec<f32> = pcm
+        .samples
+        .chunks(channels)
+        .map(|frame| frame.iter().map(|&s| s as f32).sum::<f32>() / channels as f32)
+        .collect();
+    let frames = mono.len() as u64 * OUTPUT_RATE / pcm.sample_rate as u64;
+    let header = |bpm: usize| {
+        format!(
+            "// Generated by build.rs from {}, don't edit\n\
+             BeatMap {{\n    bpm: {bpm},\n    frames: {frames},\n    beats: &[\n",
+            path.display(),
+        )
+    };
+    let footer = "    ],\n}\n";
+
+    // Loudness of each hop, and of its first difference, which is mostly highs
+    let mut energy = Vec::new();
+    let mut bright = Vec::new();
+    for hop in mono.chunks(hop_len) {
+        energy.push(hop.iter().map(|s| s * s).sum::<f32>());
+        bright.push(hop.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum::<f32>());
+    }
+
+    // Too short for even one beat at MIN_BPM, so there's no tempo to find
+    if energy.len() < HOPS_PER_SECOND * 60 / MIN_BPM {
+        return header(0) + footer;
+    }
+
+    // How much louder each hop is than the one before, on a log scale
+    // so quiet passages count as much as loud ones
+    let mut flux = vec![0.0; energy.len()];
+    for i in 1..energy.len() {
+        flux[i] = ((energy[i] + 1.0).ln() - (energy[i - 1] + 1.0).ln()).max(0.0);
+    }
+
+    let average_window = HOPS_PER_SECOND / 2;
+    let peak_window = HOPS_PER_SECOND / 20;
+    let mut onsets = Vec::new();
+    for i in 0..flux.len() {
+        let around = |window: usize| i.saturating_sub(window)..(i + window + 1).min(flux.len());
+        let average =
+            flux[around(average_window)].iter().sum::<f32>() / around(average_window).len() as f32;
+        let peak = flux[around(peak_window)].iter().all(|&f| f <= flux[i]);
+        if peak && flux[i] > average * ONSET_THRESHOLD && flux[i] > 0.1 {
+            onsets.push(Onset {
+                hop: i,
+                strength: flux[i],
+                brightness: bright[i] / (energy[i] + 1.0),
+            });
+        }
+    }
+
+    // The tempo is whichever beat length the onsets repeat at the most
+    let mut best = (0.0, HOPS_PER_SECOND * 60 / 120);
+    for lag in HOPS_PER_SECOND * 60 / MAX_BPM..=HOPS_PER_SECOND * 60 / MIN_BPM {
+        let score = (0..flux.len().saturating_sub(lag))
+            .map(|i| flux[i] * flux[i + lag])
+            .sum::<f32>()
+            / flux.len().saturating_sub(lag).max(1) as f32;
+        if score > best.0 {
+            best = (score, lag);
+        }
+    }
+    let period = best.1 as f32;
+
+    // Walk the song a beat at a time from the first onset,
+    // letting each onset we land near pull the beat into line
+    let mut beats = Vec::new();
+    if let Some(first) = onsets.first() {
+        let mut at = first.hop as f32;
+        while at < flux.len() as f32 {
+            let window = period * SNAP_WINDOW;
+            let nearest = onsets
+                .iter()
+                .filter(|o| (o.hop as f32 - at).abs() <= window)
+                .max_by(|a, b| a.strength.total_cmp(&b.strength));
+            if let Some(onset) = nearest {
+                at = onset.hop as f32;
+                beats.push(onset);
+            }
+            at += period;
+        }
+    }
+
+    // Split the beats into three equal lanes, darkest on the left
+    let mut by_brightness: Vec<f32> = beats.iter().map(|b| b.brightness).collect();
+    by_brightness.sort_by(f32::total_cmp);
+    let third = |n: usize| {
+        by_brightness
+            .get(by_brightness.len() * n / 3)
+            .copied()
+            .unwrap_or(0.0)
+    };
+    let (low, high) = (third(1), third(2));
+
+    let mut out = header(HOPS_PER_SECOND * 60 / best.1);
+    for beat in beats {
+        let frame = (beat.hop * hop_len) as u64 * OUTPUT_RATE / pcm.sample_rate as u64;
+        let lane = if beat.brightness < low {
+            -1
+        } else if beat.brightness < high {
+            0
+        } else {
+            1
+        };
+        out += &format!("        Beat {{ frame: {frame}, lane: {lane} }},\n");
+    }
+    out += footer;
+    out
+}
@@ -0,0 +1,81 @@
+// The IMA/DVI ADPCM codec itself, one 4 bit nibble per 16 bit sample.
+// See https://wiki.multimedia.cx/index.php/IMA_ADPCM
+// This file has no dependencies, because build.rs includes it too
+// to encode our assets, which keeps the encoder and decoder in lockstep.
+
+const INDEX_TABLE: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+const STEP_TABLE: [i16; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+pub const MAX_STEP_INDEX: u8 = STEP_TABLE.len() as u8 - 1;
+
+// One channel's worth of decoder state.
+// Every block starts by storing this for each channel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImaState {
+    pub predictor: i16,
+    pub step_index: u8,
+}
+
+impl ImaState {
+    pub fn decode(&mut self, nibble: u8) -> i16 {
+        let step = STEP_TABLE[self.step_index as usize] as i32;
+
+        // step * (nibble magnitude + 0.5) / 4, the way everyone else rounds it
+        let mut diff = step >> 3;
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 8 != 0 {
+            diff = -diff;
+        }
+
+        self.predictor =
+            (self.predictor as i32 + diff).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        let index = self.step_index as i32 + INDEX_TABLE[nibble as usize & 0xF] as i32;
+        self.step_index = index.clamp(0, MAX_STEP_INDEX as i32) as u8;
+
+        self.predictor
+    }
+
+    // Picks the nibble that gets the decoder closest to sample,
+    // and updates our state exactly the way the decoder will.
+    // Only build.rs encodes, the kernel just decodes.
+    #[allow(dead_code)]
+    pub fn encode(&mut self, sample: i16) -> u8 {
+        let step = STEP_TABLE[self.step_index as usize] as i32;
+        let mut diff = sample as i32 - self.predictor as i32;
+
+        let mut nibble = 0;
+        if diff < 0 {
+            nibble = 8;
+            diff = -diff;
+        }
+        if diff >= step {
+            nibble |= 4;
+            diff -= step;
+        }
+        if diff >= step >> 1 {
+            nibble |= 2;
+            diff -= step >> 1;
+        }
+        if diff >= step >> 2 {
+            nibble |= 1;
+        }
+
+        self.decode(nibble);
+        nibble
+    }
+}
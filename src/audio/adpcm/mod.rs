@@ -0,0 +1,176 @@
+use super::{
+    wav::{Encoding, Wav, IMA_GROUP_BYTES, IMA_HEADER_BYTES_PER_CHANNEL, IMA_SAMPLES_PER_GROUP},
+    Ending, Frame, FrameSource, SAMPLE_RATE,
+};
+use ima::ImaState;
+
+pub mod ima;
+
+// Plays IMA ADPCM .wav files, which are a quarter the size of 16 bit PCM.
+// build.rs makes these from the PCM assets in music/, and it's also what
+// `ffmpeg -i in.wav -c:a adpcm_ima_wav out.wav` makes.
+// See https://wiki.multimedia.cx/index.php/Microsoft_IMA_ADPCM
+//
+// ADPCM only stores the difference from the last sample, so there's no
+// jumping around in it. We decode one frame at a time as the refill asks for
+// them, and only ever go backwards to the start of a block, to loop.
+
+// Walks the frames of the file in order
+struct Decoder<'a> {
+    wav: Wav<'a>,
+    frames_per_block: usize,
+    frames: usize,
+    // Index of the frame next() returns
+    at: usize,
+    state: [ImaState; 2],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(wav: Wav<'a>) -> Self {
+        let Encoding::ImaAdpcm { frames_per_block } = wav.encoding else {
+            panic!("not an IMA ADPCM wav");
+        };
+        Self {
+            wav,
+            frames_per_block,
+            frames: wav.frames(),
+            at: 0,
+            state: [ImaState::default(); 2],
+        }
+    }
+
+    // Decodes from the start of frame's block up to it
+    fn seek(&mut self, frame: usize) {
+        self.at = frame / self.frames_per_block * self.frames_per_block;
+        while self.at < frame {
+            self.next();
+        }
+    }
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.at >= self.frames {
+            return None;
+        }
+
+        let channels = self.wav.channels as usize;
+        let block_at = self.at / self.frames_per_block * self.wav.block_align();
+        let block = &self.wav.data()[block_at..];
+        let in_block = self.at % self.frames_per_block;
+        self.at += 1;
+
+        let mut samples = [0; 2];
+        for (c, sample) in samples[..channels].iter_mut().enumerate() {
+            let state = &mut self.state[c];
+            if in_block == 0 {
+                let header = &block[c * IMA_HEADER_BYTES_PER_CHANNEL..];
+                state.predictor = i16::from_le_bytes([header[0], header[1]]);
+                state.step_index = header[2].min(ima::MAX_STEP_INDEX);
+                *sample = state.predictor;
+            } else {
+                let i = in_block - 1;
+                let group = i / IMA_SAMPLES_PER_GROUP;
+                let in_group = i % IMA_SAMPLES_PER_GROUP;
+                let byte = block[IMA_HEADER_BYTES_PER_CHANNEL * channels
+                    + (group * channels + c) * IMA_GROUP_BYTES
+                    + in_group / 2];
+                // low nibble first
+                let nibble = if in_group % 2 == 0 {
+                    byte & 0xF
+                } else {
+                    byte >> 4
+                };
+                *sample = state.decode(nibble);
+            }
+        }
+
+        if channels == 1 {
+            samples[1] = samples[0];
+        }
+        Some(samples)
+    }
+}
+
+// Like wav::WavSource, converts to SAMPLE_RATE with linear interpolation,
+// but only ever looking at the two frames we decoded last.
+pub struct AdpcmSource<'a> {
+    decoder: Decoder<'a>,
+    ending: Ending,
+    finished: bool,
+    // Output is between these two, 'frac' of the way to next
+    current: Frame,
+    next: Frame,
+    // 16.16 fixed point, in frames of the file
+    frac: u32,
+    step: u32,
+}
+
+const FRACTION_BITS: u32 = 16;
+const ONE: u32 = 1 << FRACTION_BITS;
+
+impl<'a> AdpcmSource<'a> {
+    // wav.encoding must be Encoding::ImaAdpcm
+    pub fn new(wav: Wav<'a>, ending: Ending) -> Self {
+        let mut source = Self {
+            decoder: Decoder::new(wav),
+            ending,
+            finished: false,
+            current: [0, 0],
+            next: [0, 0],
+            frac: 0,
+            step: (((wav.sample_rate as u64) << FRACTION_BITS) / SAMPLE_RATE as u64) as u32,
+        };
+        match source.decoder.next() {
+            Some(first) => {
+                source.next = first;
+                source.advance();
+            }
+            None => source.finished = true,
+        }
+        source
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    fn advance(&mut self) {
+        self.current = self.next;
+        self.next = match self.decoder.next() {
+            Some(frame) => frame,
+            None => match self.ending {
+                Ending::LoopFrom(frame) => {
+                    self.decoder.seek(frame.min(self.decoder.frames - 1));
+                    self.decoder.next().unwrap_or([0, 0])
+                }
+                Ending::Stop => {
+                    self.finished = true;
+                    self.current
+                }
+            },
+        };
+    }
+}
+
+impl FrameSource for AdpcmSource<'_> {
+    fn next_frame(&mut self) -> Frame {
+        if self.finished {
+            return [0, 0];
+        }
+
+        let frac = self.frac as i32;
+        let lerp =
+            |a: i16, b: i16| (a as i32 + (((b as i32 - a as i32) * frac) >> FRACTION_BITS)) as i16;
+        let out = [
+            lerp(self.current[0], self.next[0]),
+            lerp(self.current[1], self.next[1]),
+        ];
+
+        self.frac += self.step;
+        while self.frac >= ONE && !self.finished {
+            self.frac -= ONE;
+            self.advance();
+        }
+
+        out
+    }
+}
@@ -0,0 +1,141 @@
+use super::{Frame, SAMPLE_RATE};
+use crate::trig::sin_cos;
+
+// Measures what's coming out of the speakers, for the visualiser HUD:
+// how loud each channel is, and roughly how loud each part of the spectrum is.
+// Each band is a Goertzel filter, which works out a single bin of a DFT,
+// see https://en.wikipedia.org/wiki/Goertzel_algorithm
+// A handful of them is far cheaper than a whole FFT when we only want a few bars.
+
+// Centres of the bands, two thirds of an octave apart
+const BAND_HZ: [u32; BANDS] = [
+    63, 100, 160, 250, 400, 630, 1000, 1600, 2500, 4000, 6300, 10000,
+];
+pub const BANDS: usize = 12;
+
+// How many of the latest frames to look at, about 21ms
+pub const WINDOW_FRAMES: usize = 1024;
+
+// Every band looks at this many cycles of its frequency (up to WINDOW_FRAMES),
+// so each one is about as wide as the gap to its neighbours
+// and a tone between two bands still shows up in both
+const CYCLES_PER_BAND: u32 = 7;
+
+// Levels go from 0 (this far below full scale, or quieter) to 255 (full scale)
+const LEVEL_RANGE_DB: f32 = 48.0;
+// How far a level falls per update when the sound gets quieter,
+// so the bars don't flicker too much to read
+const LEVEL_FALL: u8 = 16;
+
+const COEFFICIENT_BITS: u32 = 14;
+
+// Everything out of 255, see LEVEL_RANGE_DB
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Levels {
+    // Left then right
+    pub channels: [u8; 2],
+    // Lowest band first
+    pub bands: [u8; BANDS],
+}
+
+pub struct Analyser {
+    // 2cos(w) for each band, as 2.14 fixed point
+    coefficients: [i32; BANDS],
+    window_frames: [usize; BANDS],
+    levels: Levels,
+}
+
+impl Analyser {
+    pub fn new() -> Self {
+        let mut coefficients = [0; BANDS];
+        let mut window_frames = [0; BANDS];
+        for (band, hz) in BAND_HZ.iter().enumerate() {
+            let w = 2.0 * core::f32::consts::PI * *hz as f32 / SAMPLE_RATE as f32;
+            let (_, cos) = sin_cos(w);
+            coefficients[band] = (2.0 * cos * (1 << COEFFICIENT_BITS) as f32) as i32;
+            window_frames[band] =
+                ((CYCLES_PER_BAND * SAMPLE_RATE / hz) as usize).min(WINDOW_FRAMES);
+        }
+        Self {
+            coefficients,
+            window_frames,
+            levels: Levels::default(),
+        }
+    }
+
+    // frames are the latest WINDOW_FRAMES that were played, oldest first.
+    // Levels jump up straight away but fall back slowly, like a real meter.
+    pub fn update(&mut self, frames: &[Frame; WINDOW_FRAMES]) -> Levels {
+        for c in 0..2 {
+            let sum_squares: u64 = frames
+                .iter()
+                .map(|f| (f[c] as i64 * f[c] as i64) as u64)
+                .sum();
+            let mean_square = sum_squares as f32 / WINDOW_FRAMES as f32;
+            // RMS, but in the log domain half the log of the mean square is the same thing
+            let level = to_level(log2(mean_square) / 2.0);
+            fall_to(&mut self.levels.channels[c], level);
+        }
+
+        for band in 0..BANDS {
+            let len = self.window_frames[band];
+            let level = to_level(self.goertzel(band, &frames[WINDOW_FRAMES - len..]));
+            fall_to(&mut self.levels.bands[band], level);
+        }
+
+        self.levels
+    }
+
+    // log2 of the amplitude of the band's frequency in frames,
+    // where a full scale sine is 15
+    fn goertzel(&self, band: usize, frames: &[Frame]) -> f32 {
+        let coefficient = self.coefficients[band] as i64;
+        let len = frames.len() as i64;
+        let (mut s1, mut s2) = (0i64, 0i64);
+        for (n, frame) in frames.iter().enumerate() {
+            // A triangular window, so the edges of the window don't smear
+            // loud bass across every band. It's cheaper than a Hann window
+            // and good enough for text mode.
+            let n = n as i64;
+            let window = (n + 1).min(len - n);
+            let mono = (frame[0] as i64 + frame[1] as i64) / 2;
+            let x = mono * window * 2 / len;
+            let s0 = x + ((coefficient * s1) >> COEFFICIENT_BITS) - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+
+        // Squared magnitude of the bin. The products overflow i64 for
+        // loud bass, so this bit is done in i128.
+        let (s1, s2, coefficient) = (s1 as i128, s2 as i128, coefficient as i128);
+        let power = s1 * s1 + s2 * s2 - ((coefficient * s1 * s2) >> COEFFICIENT_BITS);
+        // The bin's magnitude is len / 4 times the amplitude,
+        // the window having taken away half of it
+        log2(power.max(0) as f32) / 2.0 - log2(len as f32 / 4.0)
+    }
+}
+
+// From log2 of an amplitude, where 15 is full scale
+fn to_level(log2_amplitude: f32) -> u8 {
+    // 20 * log10(2)
+    const DB_PER_DOUBLING: f32 = 6.0206;
+    let db = (log2_amplitude - 15.0) * DB_PER_DOUBLING;
+    ((db + LEVEL_RANGE_DB) / LEVEL_RANGE_DB * 255.0).clamp(0.0, 255.0) as u8
+}
+
+fn fall_to(level: &mut u8, new: u8) {
+    *level = new.max(level.saturating_sub(LEVEL_FALL));
+}
+
+// core has no log2 without std. Straight from the float's bits, which is
+// the exponent plus a straight line between powers of two for the mantissa.
+// It's off by at most half a dB, which nobody will see on a bar 4 rows high.
+fn log2(x: f32) -> f32 {
+    if x <= 0.0 {
+        return -127.0;
+    }
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as f32 - 127.0;
+    let mantissa = (bits & 0x7F_FFFF) as f32 / (1 << 23) as f32;
+    exponent + mantissa
+}
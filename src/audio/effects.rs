@@ -0,0 +1,334 @@
+use super::{Frame, SAMPLE_RATE};
+use crate::trig::sin_cos;
+
+// An effects chain for the music: high pass, low pass, bitcrusher, then echo.
+// Every parameter glides to its new value over however long the game asks for,
+// so effects can be swept in and out (e.g. muffling the music while paused)
+// without clicks. Everything per sample is integer math. The filters need
+// some trig to work out their coefficients, but only every few frames,
+// and only while their cutoff is actually moving.
+
+// A parameter that ramps linearly towards its target, one step per frame.
+// Values are kept with 8 fractional bits so slow ramps still move.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Param {
+    value: i32,
+    target: i32,
+    step: i32,
+}
+
+const PARAM_FRACTION_BITS: u32 = 8;
+
+impl Param {
+    pub(super) const fn new(value: i32) -> Self {
+        Self {
+            value: value << PARAM_FRACTION_BITS,
+            target: value << PARAM_FRACTION_BITS,
+            step: 0,
+        }
+    }
+
+    pub(super) fn set(&mut self, target: i32, ms: u32) {
+        self.target = target << PARAM_FRACTION_BITS;
+        let frames = (ms * SAMPLE_RATE / 1000).max(1) as i32;
+        self.step = ((self.target - self.value) / frames).abs().max(1);
+    }
+
+    pub(super) fn get(&self) -> i32 {
+        self.value >> PARAM_FRACTION_BITS
+    }
+
+    pub(super) fn settled(&self) -> bool {
+        self.value == self.target
+    }
+
+    pub(super) fn tick(&mut self) {
+        if self.value < self.target {
+            self.value = (self.value + self.step).min(self.target);
+        } else if self.value > self.target {
+            self.value = (self.value - self.step).max(self.target);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterKind {
+    LowPass,
+    HighPass,
+}
+
+// Past these the filters would barely do anything, so they're skipped
+const LOWPASS_OFF_HZ: i32 = 20_000;
+const HIGHPASS_OFF_HZ: i32 = 20;
+
+// How many frames a moving cutoff keeps the same coefficients for
+const COEFFICIENT_INTERVAL: u32 = 32;
+
+// Coefficients are 4.28 fixed point
+const COEFFICIENT_BITS: u32 = 28;
+const SIGNAL_FRACTION_BITS: u32 = 8;
+
+// A two pole filter, from the Audio EQ Cookbook:
+// https://www.w3.org/TR/audio-eq-cookbook/
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    kind: FilterKind,
+    cutoff: Param,
+    // b0, b1, b2, a1, a2, already divided by a0
+    coefficients: [i32; 5],
+    // Last two inputs and outputs, per channel, with SIGNAL_FRACTION_BITS
+    // extra bits, since rounding errors get amplified in a low cutoff's feedback
+    x: [[i32; 2]; 2],
+    y: [[i32; 2]; 2],
+    frames_until_update: u32,
+}
+
+impl Biquad {
+    const fn new(kind: FilterKind) -> Self {
+        let off = match kind {
+            FilterKind::LowPass => LOWPASS_OFF_HZ,
+            FilterKind::HighPass => HIGHPASS_OFF_HZ,
+        };
+        Self {
+            kind,
+            cutoff: Param::new(off),
+            coefficients: [0; 5],
+            x: [[0; 2]; 2],
+            y: [[0; 2]; 2],
+            frames_until_update: 0,
+        }
+    }
+
+    fn bypassed(&self) -> bool {
+        let off = match self.kind {
+            FilterKind::LowPass => self.cutoff.get() >= LOWPASS_OFF_HZ,
+            FilterKind::HighPass => self.cutoff.get() <= HIGHPASS_OFF_HZ,
+        };
+        off && self.cutoff.settled()
+    }
+
+    fn update_coefficients(&mut self) {
+        // Butterworth, so no resonant peak
+        const Q: f32 = core::f32::consts::FRAC_1_SQRT_2;
+
+        let hz = self.cutoff.get().clamp(HIGHPASS_OFF_HZ, LOWPASS_OFF_HZ) as f32;
+        let w0 = 2.0 * core::f32::consts::PI * hz / SAMPLE_RATE as f32;
+        let (sin, cos) = sin_cos(w0);
+        let alpha = sin / (2.0 * Q);
+
+        let (b0, b1, b2) = match self.kind {
+            FilterKind::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0),
+            FilterKind::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0),
+        };
+        let a0 = 1.0 + alpha;
+        let a1 = -2.0 * cos;
+        let a2 = 1.0 - alpha;
+
+        let fixed = |c: f32| (c / a0 * (1 << COEFFICIENT_BITS) as f32) as i32;
+        self.coefficients = [fixed(b0), fixed(b1), fixed(b2), fixed(a1), fixed(a2)];
+    }
+
+    fn process(&mut self, frame: Frame) -> Frame {
+        self.cutoff.tick();
+        if self.bypassed() {
+            // Start from silence next time, rather than from stale history
+            self.x = [[0; 2]; 2];
+            self.y = [[0; 2]; 2];
+            self.frames_until_update = 0;
+            return frame;
+        }
+
+        if self.frames_until_update == 0 {
+            self.update_coefficients();
+            self.frames_until_update = if self.cutoff.settled() {
+                u32::MAX
+            } else {
+                COEFFICIENT_INTERVAL
+            };
+        }
+        self.frames_until_update -= 1;
+
+        let [b0, b1, b2, a1, a2] = self.coefficients.map(|c| c as i64);
+        let mut out = [0; 2];
+        for c in 0..2 {
+            let x0 = (frame[c] as i64) << SIGNAL_FRACTION_BITS;
+            let [x1, x2] = self.x[c].map(|v| v as i64);
+            let [y1, y2] = self.y[c].map(|v| v as i64);
+            let y0 = (b0 * x0 + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2) >> COEFFICIENT_BITS;
+
+            self.x[c] = [x0 as i32, x1 as i32];
+            self.y[c] = [y0 as i32, y1 as i32];
+
+            let y0 = y0 >> SIGNAL_FRACTION_BITS;
+            out[c] = y0.clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+        }
+        out
+    }
+
+    fn set_cutoff(&mut self, hz: u32, ms: u32) {
+        self.cutoff.set(hz as i32, ms);
+        self.frames_until_update = 0;
+    }
+}
+
+// Out of 256
+const WET_ONE: i32 = 256;
+
+// Longest echo delay, which is also how much memory the delay line takes
+const MAX_ECHO_MS: u32 = 400;
+const MAX_ECHO_FRAMES: usize = (MAX_ECHO_MS * SAMPLE_RATE / 1000) as usize;
+
+// A feedback delay line. The delay itself jumps rather than glides,
+// since sliding it would bend the pitch of everything in the line.
+struct Echo {
+    line: [Frame; MAX_ECHO_FRAMES],
+    at: usize,
+    delay_frames: usize,
+    // Both out of WET_ONE
+    feedback: Param,
+    wet: Param,
+}
+
+impl Echo {
+    const fn new() -> Self {
+        Self {
+            line: [[0, 0]; MAX_ECHO_FRAMES],
+            at: 0,
+            delay_frames: MAX_ECHO_FRAMES,
+            feedback: Param::new(0),
+            wet: Param::new(0),
+        }
+    }
+
+    fn process(&mut self, frame: Frame) -> Frame {
+        self.feedback.tick();
+        self.wet.tick();
+        let feedback = self.feedback.get();
+        let wet = self.wet.get();
+
+        let read = (self.at + MAX_ECHO_FRAMES - self.delay_frames) % MAX_ECHO_FRAMES;
+        let delayed = self.line[read];
+
+        let mut out = [0; 2];
+        for c in 0..2 {
+            let echo = delayed[c] as i32;
+            let into_line = frame[c] as i32 + echo * feedback / WET_ONE;
+            self.line[self.at][c] = into_line.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            let mixed = frame[c] as i32 + echo * wet / WET_ONE;
+            out[c] = mixed.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+
+        self.at = (self.at + 1) % MAX_ECHO_FRAMES;
+        out
+    }
+}
+
+// Throws away resolution and sample rate, for that broken radio sound.
+// Crossfaded with the clean signal, since bit depth can't glide smoothly.
+struct Bitcrush {
+    // 1..=16
+    bits: u8,
+    // Hold each sample for this many frames, 1 is off
+    downsample: u8,
+    wet: Param,
+    held: Frame,
+    hold_left: u8,
+}
+
+impl Bitcrush {
+    const fn new() -> Self {
+        Self {
+            bits: 16,
+            downsample: 1,
+            wet: Param::new(0),
+            held: [0, 0],
+            hold_left: 0,
+        }
+    }
+
+    fn process(&mut self, frame: Frame) -> Frame {
+        self.wet.tick();
+        let wet = self.wet.get();
+        if wet == 0 {
+            return frame;
+        }
+
+        if self.hold_left == 0 {
+            let mask = !((1i32 << (16 - self.bits as u32)) - 1);
+            self.held = frame.map(|s| (s as i32 & mask) as i16);
+            self.hold_left = self.downsample;
+        }
+        self.hold_left -= 1;
+
+        let mut out = [0; 2];
+        for c in 0..2 {
+            let dry = frame[c] as i32;
+            let crushed = self.held[c] as i32;
+            out[c] = (dry + (crushed - dry) * wet / WET_ONE) as i16;
+        }
+        out
+    }
+}
+
+pub struct Effects {
+    highpass: Biquad,
+    lowpass: Biquad,
+    crush: Bitcrush,
+    echo: Echo,
+}
+
+impl Effects {
+    // Starts with everything off, so the music passes through untouched
+    pub const fn new() -> Self {
+        Self {
+            highpass: Biquad::new(FilterKind::HighPass),
+            lowpass: Biquad::new(FilterKind::LowPass),
+            crush: Bitcrush::new(),
+            echo: Echo::new(),
+        }
+    }
+
+    // Everything above hz gets cut, e.g. ~500 for music through a wall.
+    // 20kHz or more turns it off.
+    pub fn set_lowpass(&mut self, hz: u32, ms: u32) {
+        self.lowpass.set_cutoff(hz.min(LOWPASS_OFF_HZ as u32), ms);
+    }
+
+    // Everything below hz gets cut, e.g. ~1kHz for a tinny radio.
+    // 20Hz or less turns it off.
+    pub fn set_highpass(&mut self, hz: u32, ms: u32) {
+        self.highpass.set_cutoff(hz.max(HIGHPASS_OFF_HZ as u32), ms);
+    }
+
+    // feedback and wet are out of 256, and wet 0 turns it off.
+    // Feedback close to 256 rings for a long time.
+    pub fn set_echo(&mut self, delay_ms: u32, feedback: u8, wet: u8, ms: u32) {
+        let delay_frames = (delay_ms.min(MAX_ECHO_MS) * SAMPLE_RATE / 1000) as usize;
+        self.echo.delay_frames = delay_frames.max(1);
+        self.echo.feedback.set(feedback as i32, ms);
+        self.echo.wet.set(wet as i32, ms);
+    }
+
+    // bits is clamped to 1..=16, and wet (out of 256) 0 turns it off
+    pub fn set_bitcrush(&mut self, bits: u8, downsample: u8, wet: u8, ms: u32) {
+        self.crush.bits = bits.clamp(1, 16);
+        self.crush.downsample = downsample.max(1);
+        self.crush.wet.set(wet as i32, ms);
+    }
+
+    // Fades every effect back out
+    pub fn clear(&mut self, ms: u32) {
+        self.set_lowpass(LOWPASS_OFF_HZ as u32, ms);
+        self.set_highpass(HIGHPASS_OFF_HZ as u32, ms);
+        self.echo.feedback.set(0, ms);
+        self.echo.wet.set(0, ms);
+        self.crush.wet.set(0, ms);
+    }
+
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        let frame = self.highpass.process(frame);
+        let frame = self.lowpass.process(frame);
+        let frame = self.crush.process(frame);
+        self.echo.process(frame)
+    }
+}
@@ -0,0 +1,84 @@
+use super::{Ending, Frame, FrameSource, Track, TrackError, SAMPLE_RATE};
+
+// Adaptive music: extra stems (drums, bass, lead, ...) that play in sync with
+// a cue's main track and fade in and out as the game gets more intense.
+// Every stem is rendered on every frame whether we can hear it or not,
+// so as long as they are all the same length they never drift apart.
+
+pub const MAX_LAYERS: usize = 3;
+
+// One stem of a cue, see playlist::Cue
+#[derive(Debug, Clone, Copy)]
+pub struct Layer<'a> {
+    pub bytes: &'a [u8],
+    // Heard once the intensity is at least this
+    pub fades_in_at: u8,
+}
+
+// Layers take this long to fade all the way in or out
+const FADE_MS: u32 = 750;
+const GAIN_BITS: u32 = 24;
+const GAIN_ONE: u32 = 1 << GAIN_BITS;
+const GAIN_STEP: u32 = GAIN_ONE / (FADE_MS * SAMPLE_RATE / 1000);
+
+struct Stem<'a> {
+    track: Track<'a>,
+    fades_in_at: u8,
+    gain: u32,
+}
+
+pub struct Layers<'a> {
+    stems: [Option<Stem<'a>>; MAX_LAYERS],
+    intensity: u8,
+}
+
+impl<'a> Layers<'a> {
+    // Any layers past MAX_LAYERS are ignored.
+    // Layers that the intensity already calls for start out audible.
+    pub fn load(layers: &[Layer<'a>], ending: Ending, intensity: u8) -> Result<Self, TrackError> {
+        let mut stems = [const { None }; MAX_LAYERS];
+        for (stem, layer) in stems.iter_mut().zip(layers) {
+            *stem = Some(Stem {
+                track: Track::from_bytes(layer.bytes, ending)?,
+                fades_in_at: layer.fades_in_at,
+                gain: if intensity >= layer.fades_in_at {
+                    GAIN_ONE
+                } else {
+                    0
+                },
+            });
+        }
+        Ok(Self { stems, intensity })
+    }
+
+    // 0 is calm, 255 is as hectic as it gets
+    pub fn set_intensity(&mut self, intensity: u8) {
+        self.intensity = intensity;
+    }
+}
+
+impl FrameSource for Layers<'_> {
+    fn next_frame(&mut self) -> Frame {
+        let mut left = 0i64;
+        let mut right = 0i64;
+        for stem in self.stems.iter_mut().flatten() {
+            let target = if self.intensity >= stem.fades_in_at {
+                GAIN_ONE
+            } else {
+                0
+            };
+            if stem.gain < target {
+                stem.gain = (stem.gain + GAIN_STEP).min(target);
+            } else {
+                stem.gain = stem.gain.saturating_sub(GAIN_STEP).max(target);
+            }
+
+            let [l, r] = stem.track.next_frame();
+            left += l as i64 * stem.gain as i64;
+            right += r as i64 * stem.gain as i64;
+        }
+
+        let scale = |x: i64| (x >> GAIN_BITS).clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+        [scale(left), scale(right)]
+    }
+}
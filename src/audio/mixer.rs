@@ -0,0 +1,149 @@
+use super::{
+    effects::Effects,
+    playlist::{Cue, Playlist},
+    spatial::{Placement, GAIN_ONE},
+    synth::{Sfx, Voice},
+    varispeed::Varispeed,
+    Frame, FrameSource,
+};
+
+// How many sound effects can play over the music at once.
+// Starting another steals the voice that has been playing longest.
+const MAX_VOICES: usize = 8;
+
+// Sound effects are mixed in at this fraction of full scale (out of 256),
+// so a few of them on top of loud music don't clip too badly.
+const SFX_GAIN: i32 = 160;
+
+// The music with any sound effects that are playing layered on top.
+// This is what gets handed to MusicOutput, so effects go out through
+// whichever device is playing the music, with the same latency.
+pub struct Mixer<'a> {
+    music: Playlist<'a>,
+    music_speed: Varispeed,
+    // Only the music goes through these, sound effects stay clear
+    music_effects: Effects,
+    voices: [Option<Voice>; MAX_VOICES],
+    // Which sound started in each slot, so a stale VoiceHandle
+    // can't move a sound that has since stolen its slot
+    started: [u32; MAX_VOICES],
+    next_start: u32,
+}
+
+// Refers to one sound started by play_sfx_at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceHandle {
+    slot: usize,
+    start: u32,
+}
+
+impl<'a> Mixer<'a> {
+    pub fn new(music: Playlist<'a>) -> Self {
+        Self {
+            music,
+            music_speed: Varispeed::new(),
+            music_effects: Effects::new(),
+            voices: [None; MAX_VOICES],
+            started: [0; MAX_VOICES],
+            next_start: 0,
+        }
+    }
+
+    pub fn music(&mut self) -> &mut Playlist<'a> {
+        &mut self.music
+    }
+
+    pub fn music_effects(&mut self) -> &mut Effects {
+        &mut self.music_effects
+    }
+
+    // Speeds the music (tempo and pitch both) up or down,
+    // gliding there over ms. Sound effects aren't affected.
+    pub fn set_playback_rate(&mut self, rate: f32, ms: u32) {
+        self.music_speed.set_rate(rate, ms);
+    }
+
+    // How far into cue the listener is, in frames, see Playlist::position.
+    // frames_queued is how far behind the mix the speakers are,
+    // see MusicOutput::frames_queued.
+    pub fn music_position(&self, cue: &Cue, frames_queued: u32) -> Option<usize> {
+        let mixed = self.music.position(cue)?;
+        let queued = self.music_speed.source_frames(frames_queued) as usize;
+        Some(mixed.saturating_sub(queued))
+    }
+
+    pub fn play_sfx(&mut self, sfx: &Sfx) {
+        self.play_sfx_at(sfx, Placement::CENTER);
+    }
+
+    // For sounds that come from somewhere in the world, see audio::spatial.
+    // Keep the handle to move the sound around while it plays.
+    pub fn play_sfx_at(&mut self, sfx: &Sfx, placement: Placement) -> VoiceHandle {
+        let slot = match self.voices.iter().position(|v| v.is_none()) {
+            Some(free) => free,
+            None => {
+                let mut oldest = 0;
+                for (i, voice) in self.voices.iter().enumerate() {
+                    let elapsed = voice.map_or(0, |v| v.elapsed());
+                    if elapsed > self.voices[oldest].map_or(0, |v| v.elapsed()) {
+                        oldest = i;
+                    }
+                }
+                oldest
+            }
+        };
+
+        let mut voice = Voice::new(sfx);
+        voice.set_placement(placement);
+        self.voices[slot] = Some(voice);
+
+        self.next_start = self.next_start.wrapping_add(1);
+        self.started[slot] = self.next_start;
+        VoiceHandle {
+            slot,
+            start: self.next_start,
+        }
+    }
+
+    // Returns false once the sound has finished (or been cut off),
+    // after which the handle is no use
+    pub fn place(&mut self, handle: VoiceHandle, placement: Placement) -> bool {
+        if self.started[handle.slot] != handle.start {
+            return false;
+        }
+        match &mut self.voices[handle.slot] {
+            Some(voice) => {
+                voice.set_placement(placement);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl FrameSource for Mixer<'_> {
+    fn next_frame(&mut self) -> Frame {
+        let music = self.music_speed.next_frame(&mut self.music);
+        let [left, right] = self.music_effects.process(music);
+
+        let mut sfx_left = 0;
+        let mut sfx_right = 0;
+        for slot in self.voices.iter_mut() {
+            if let Some(voice) = slot {
+                let placement = voice.placement();
+                let sample = voice.next_sample() as i32;
+                sfx_left += sample * placement.left as i32 / GAIN_ONE as i32;
+                sfx_right += sample * placement.right as i32 / GAIN_ONE as i32;
+                if voice.finished() {
+                    *slot = None;
+                }
+            }
+        }
+
+        let mix = |music: i16, sfx: i32| {
+            let sfx = sfx * SFX_GAIN / 256;
+            (music as i32 + sfx).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+        };
+        [mix(left, sfx_left), mix(right, sfx_right)]
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    isa::sb16::{music_loop::MusicLoop as Sb16MusicLoop, Sb16},
+    pc_speaker::{
+        melody::{MelodyPlayer, Note},
+        pwm::PwmLoop,
+        PcSpeaker,
+    },
+    pci::audio_ac97::{
+        music_loop::{MusicLoop, PlaybackStats, RingConfig},
+        AudioAc97,
+    },
+    phys_alloc::PhysAllocator,
+};
+use adpcm::AdpcmSource;
+use pcm::PcmLoop;
+use tracker::{player::ModPlayer, ModError, Module};
+use wav::{Encoding, Wav, WavError, WavSource};
+
+pub mod adpcm;
+pub mod analyser;
+pub mod effects;
+pub mod layers;
+pub mod mixer;
+pub mod pcm;
+pub mod playlist;
+pub mod spatial;
+pub mod synth;
+pub mod tracker;
+pub mod varispeed;
+pub mod wav;
+
+// Every output device is run at this rate, and every source is
+// expected to produce frames at it, converting if they need to.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+// Interleaved left, right
+pub type Frame = [i16; 2];
+
+pub trait FrameSource {
+    fn next_frame(&mut self) -> Frame;
+}
+
+// What a track does when it gets to the end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ending {
+    // Jump back to this frame (counted in the file's own sample rate),
+    // so anything before it is an intro that only plays once.
+    // Modules always go back to the restart position in their header instead.
+    LoopFrom(usize),
+    // Go silent, e.g. for a stinger
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackError {
+    Wav(WavError),
+    Module(ModError),
+}
+
+// A piece of music from an embedded asset.
+// ModPlayer is much bigger than the others, but there's no heap to box it on.
+#[allow(clippy::large_enum_variant)]
+pub enum Track<'a> {
+    Raw(PcmLoop<'a>),
+    Wav(WavSource<'a>),
+    Adpcm(AdpcmSource<'a>),
+    Module(ModPlayer<'a>),
+}
+
+impl<'a> Track<'a> {
+    // .wav files are recognized by their RIFF header, and .mod files by the
+    // signature after their sample headers. Anything else is assumed to be
+    // headerless 16 bit stereo at SAMPLE_RATE, like our original .raw assets.
+    pub fn from_bytes(bytes: &'a [u8], ending: Ending) -> Result<Self, TrackError> {
+        if wav::is_riff(bytes) {
+            let wav = Wav::parse(bytes).map_err(TrackError::Wav)?;
+            match wav.encoding {
+                Encoding::Pcm => Ok(Self::Wav(WavSource::new(wav, ending))),
+                Encoding::ImaAdpcm { .. } => Ok(Self::Adpcm(AdpcmSource::new(wav, ending))),
+            }
+        } else if tracker::is_mod(bytes) {
+            let module = Module::parse(bytes).map_err(TrackError::Module)?;
+            Ok(Self::Module(ModPlayer::new(module, ending)))
+        } else {
+            Ok(Self::Raw(PcmLoop::new(bytes, ending)))
+        }
+    }
+
+    // Only ever true for tracks with Ending::Stop
+    pub fn finished(&self) -> bool {
+        match self {
+            Self::Raw(track) => track.finished(),
+            Self::Wav(track) => track.finished(),
+            Self::Adpcm(track) => track.finished(),
+            Self::Module(track) => track.finished(),
+        }
+    }
+}
+
+impl FrameSource for Track<'_> {
+    fn next_frame(&mut self) -> Frame {
+        match self {
+            Self::Raw(track) => track.next_frame(),
+            Self::Wav(track) => track.next_frame(),
+            Self::Adpcm(track) => track.next_frame(),
+            Self::Module(track) => track.next_frame(),
+        }
+    }
+}
+
+// Set this to play the PCM music through the PC speaker instead of
+// the square wave melody when there is no sound card.
+// It sounds pretty rough and steals a lot of cpu time, so it is off by default.
+const PC_SPEAKER_PWM: bool = false;
+
+// How far behind the game the AC97 music is allowed to run.
+// Sound effects mixed into the music get delayed this much, but wind()
+// has to be called at least every MUSIC_LATENCY_MS / MUSIC_QUEUED_BUFFERS.
+const MUSIC_LATENCY_MS: u32 = 40;
+const MUSIC_QUEUED_BUFFERS: u8 = 4;
+
+// Whichever device we found to play music on.
+pub enum MusicOutput<'a> {
+    Ac97(MusicLoop<'a>),
+    Sb16(Sb16MusicLoop<'a>),
+    SpeakerPwm(PwmLoop),
+    SpeakerMelody(MelodyPlayer<'a>),
+}
+
+impl<'a> MusicOutput<'a> {
+    // Prefers a real sound card (PCI, then ISA), and falls back on the PC speaker,
+    // which has to play the melody instead of the PCM music
+    // unless PC_SPEAKER_PWM is set.
+    pub fn new(
+        phys_alloc: &mut PhysAllocator,
+        ac97: Option<AudioAc97>,
+        melody: &'a [Note],
+    ) -> Self {
+        if let Some(ac97) = ac97 {
+            let config = RingConfig::from_latency_ms(MUSIC_LATENCY_MS, MUSIC_QUEUED_BUFFERS);
+            return Self::Ac97(MusicLoop::new(phys_alloc, ac97, config));
+        }
+
+        if let Some(sb16) = Sb16::probe() {
+            if let Some(music) = Sb16MusicLoop::new(phys_alloc, sb16) {
+                return Self::Sb16(music);
+            }
+        }
+
+        if PC_SPEAKER_PWM {
+            Self::SpeakerPwm(PwmLoop::new(PcSpeaker::new()))
+        } else {
+            Self::SpeakerMelody(MelodyPlayer::new(PcSpeaker::new(), melody))
+        }
+    }
+
+    // The melody player ignores source, it has its own music
+    pub fn play(&mut self, source: &mut dyn FrameSource) {
+        match self {
+            Self::Ac97(music) => music.play(source),
+            Self::Sb16(music) => music.play(source),
+            Self::SpeakerPwm(music) => music.play(),
+            Self::SpeakerMelody(music) => music.play(),
+        }
+    }
+
+    // must be called every timer tick after play()
+    pub fn wind(&mut self, source: &mut dyn FrameSource) {
+        match self {
+            Self::Ac97(music) => music.wind(source),
+            Self::Sb16(music) => music.wind(source),
+            Self::SpeakerPwm(music) => music.wind(source),
+            Self::SpeakerMelody(music) => music.wind(),
+        }
+    }
+
+    // must be called as often as possible after play(),
+    // for devices that need finer timing than the timer tick
+    pub fn poll(&mut self, source: &mut dyn FrameSource) {
+        match self {
+            Self::Ac97(music) => music.wind(source),
+            Self::SpeakerPwm(music) => music.wind(source),
+            Self::Sb16(_) | Self::SpeakerMelody(_) => {}
+        }
+    }
+
+    // How far behind the source the speakers are, in frames.
+    // The PC speaker plays every frame as soon as it's pulled,
+    // and the melody player doesn't play the source at all.
+    pub fn frames_queued(&self) -> Option<u32> {
+        match self {
+            Self::Ac97(music) => Some(music.frames_queued()),
+            Self::Sb16(music) => Some(music.frames_queued()),
+            Self::SpeakerPwm(_) => Some(0),
+            Self::SpeakerMelody(_) => None,
+        }
+    }
+
+    // Fills out with the frames that just played, oldest first,
+    // and returns false if the device can't tell us.
+    // Only the AC97 has a ring we can read back.
+    pub fn recent_frames(&self, out: &mut [Frame]) -> bool {
+        match self {
+            Self::Ac97(music) => {
+                music.recent_frames(out);
+                true
+            }
+            Self::Sb16(_) | Self::SpeakerPwm(_) | Self::SpeakerMelody(_) => false,
+        }
+    }
+
+    // Only the AC97 keeps track of how playback is going
+    pub fn stats(&self) -> Option<PlaybackStats> {
+        match self {
+            Self::Ac97(music) => Some(music.stats()),
+            Self::Sb16(_) | Self::SpeakerPwm(_) | Self::SpeakerMelody(_) => None,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use super::{Ending, Frame, FrameSource};
+
+// Headerless 16 bit little endian stereo, already at SAMPLE_RATE.
+// This is what the .raw assets are, and what the AC97 eats directly.
+pub struct PcmLoop<'a> {
+    data: &'a [u8],
+    read_head: usize,
+    ending: Ending,
+    finished: bool,
+}
+
+const BYTES_PER_FRAME: usize = 4;
+
+impl<'a> PcmLoop<'a> {
+    pub fn new(data: &'a [u8], ending: Ending) -> Self {
+        Self {
+            data,
+            read_head: 0,
+            ending,
+            finished: false,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl FrameSource for PcmLoop<'_> {
+    fn next_frame(&mut self) -> Frame {
+        // A trailing partial frame is ignored
+        if self.finished || self.data.len() < BYTES_PER_FRAME {
+            return [0, 0];
+        }
+
+        let d = &self.data[self.read_head..self.read_head + BYTES_PER_FRAME];
+        let frame = [
+            i16::from_le_bytes([d[0], d[1]]),
+            i16::from_le_bytes([d[2], d[3]]),
+        ];
+
+        self.read_head += BYTES_PER_FRAME;
+        if self.read_head + BYTES_PER_FRAME > self.data.len() {
+            match self.ending {
+                Ending::LoopFrom(frame) => {
+                    let frames = self.data.len() / BYTES_PER_FRAME;
+                    self.read_head = frame.min(frames - 1) * BYTES_PER_FRAME;
+                }
+                Ending::Stop => self.finished = true,
+            }
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,249 @@
+use super::{
+    layers::{Layer, Layers},
+    Ending, Frame, FrameSource, Track, TrackError, SAMPLE_RATE,
+};
+
+// Switches between pieces of music as the game moves between screens.
+// At most three cues are ever loaded at once: the one we're switching to,
+// and up to two fading out underneath it, for when the music changes
+// again before a crossfade is over.
+
+// An embedded music asset, and how it loops
+#[derive(Debug, Clone, Copy)]
+pub struct Cue<'a> {
+    pub bytes: &'a [u8],
+    pub ending: Ending,
+    // Stems that play along with bytes depending on the intensity,
+    // see audio::layers. Empty for plain songs.
+    pub layers: &'a [Layer<'a>],
+}
+
+impl Cue<'_> {
+    // Comparing the bytes themselves would mean reading the whole asset
+    fn same_as(&self, other: &Cue) -> bool {
+        core::ptr::eq(self.bytes, other.bytes)
+            && core::ptr::eq(self.layers, other.layers)
+            && self.ending == other.ending
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Transition<'a> {
+    // A crossfade of 0 ms is a hard cut
+    Crossfade { ms: u32 },
+    // Plays the stinger over a quick fade out of whatever was playing,
+    // and starts the new cue once it is over
+    Stinger(&'a Cue<'a>),
+}
+
+// How quickly the old music gets out of the way of a stinger
+const STINGER_FADE_MS: u32 = 100;
+// Full volume, for fades
+const GAIN_ONE: i64 = 1 << 16;
+
+struct Playing<'a> {
+    cue: Cue<'a>,
+    track: Track<'a>,
+    layers: Layers<'a>,
+    // Frames played since it was loaded, loops and all
+    position: usize,
+}
+
+impl FrameSource for Playing<'_> {
+    fn next_frame(&mut self) -> Frame {
+        self.position += 1;
+        let [left, right] = self.track.next_frame();
+        let [layer_left, layer_right] = self.layers.next_frame();
+        [
+            left.saturating_add(layer_left),
+            right.saturating_add(layer_right),
+        ]
+    }
+}
+
+// A cue on its way out, from however loud it was when the music changed
+struct Fading<'a> {
+    playing: Playing<'a>,
+    // Out of GAIN_ONE
+    from_gain: i64,
+    // Both in frames
+    len: u32,
+    pos: u32,
+}
+
+impl Fading<'_> {
+    fn gain(&self) -> i64 {
+        if self.pos >= self.len {
+            0
+        } else {
+            self.from_gain * (self.len - self.pos) as i64 / self.len as i64
+        }
+    }
+}
+
+pub struct Playlist<'a> {
+    current: Option<Playing<'a>>,
+    outgoing: [Option<Fading<'a>>; 2],
+    // How far current has faded in, both in frames
+    fade_len: u32,
+    fade_pos: u32,
+    // Set while current is a stinger, for what to play once it is over
+    after_stinger: Option<Option<Cue<'a>>>,
+    intensity: u8,
+}
+
+impl<'a> Playlist<'a> {
+    // Silent until the first change()
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            outgoing: [None, None],
+            fade_len: 0,
+            fade_pos: 0,
+            after_stinger: None,
+            intensity: 0,
+        }
+    }
+
+    // Fades the current cue's layers in and out, see audio::layers.
+    // Cues started later pick up from the same intensity.
+    pub fn set_intensity(&mut self, intensity: u8) {
+        self.intensity = intensity;
+        if let Some(playing) = &mut self.current {
+            playing.layers.set_intensity(intensity);
+        }
+    }
+
+    // Switches to cue, or silence for None. If cue is already playing
+    // it just keeps going, so screens can share a song without it restarting.
+    pub fn change(
+        &mut self,
+        cue: Option<&Cue<'a>>,
+        transition: Transition<'a>,
+    ) -> Result<(), TrackError> {
+        if let (Some(cue), Some(playing)) = (cue, &self.current) {
+            if self.after_stinger.is_none() && playing.cue.same_as(cue) {
+                return Ok(());
+            }
+        }
+
+        match transition {
+            Transition::Crossfade { ms } => {
+                let incoming = cue.map(|cue| load(cue, self.intensity)).transpose()?;
+                self.fade_out_current(ms);
+                self.current = incoming;
+                self.after_stinger = None;
+            }
+            Transition::Stinger(stinger) => {
+                // Make sure the cue after the stinger will load,
+                // since we can't report an error once it is due
+                if let Some(cue) = cue {
+                    load(cue, self.intensity)?;
+                }
+                let stinger = load(stinger, self.intensity)?;
+                self.fade_out_current(STINGER_FADE_MS);
+                self.current = Some(stinger);
+                self.after_stinger = Some(cue.copied());
+            }
+        }
+        Ok(())
+    }
+
+    // How many frames of cue have been mixed since it started,
+    // or None if it isn't what's playing. Counts straight on through loops,
+    // so it's up to the caller to wrap it, see spacefox::rhythm.
+    pub fn position(&self, cue: &Cue) -> Option<usize> {
+        match &self.current {
+            Some(playing) if self.after_stinger.is_none() && playing.cue.same_as(cue) => {
+                Some(playing.position)
+            }
+            _ => None,
+        }
+    }
+
+    // Fades whatever is playing out from how loud it is right now,
+    // and the next cue in over the same time
+    fn fade_out_current(&mut self, ms: u32) {
+        let len = ms * SAMPLE_RATE / 1000;
+        let from_gain = self.current_gain();
+        let silent = self.current.is_none() && self.outgoing.iter().all(Option::is_none);
+        if let Some(playing) = self.current.take() {
+            // Anything quieter than this is already nearly gone
+            let quietest = (0..self.outgoing.len())
+                .min_by_key(|&i| self.outgoing[i].as_ref().map_or(0, Fading::gain))
+                .unwrap_or(0);
+            self.outgoing[quietest] = Some(Fading {
+                playing,
+                from_gain,
+                len,
+                pos: 0,
+            });
+        }
+        // Nothing to crossfade from, so the new cue starts at full volume
+        self.fade_len = if silent { 0 } else { len };
+        self.fade_pos = 0;
+    }
+
+    // How loud current is, out of GAIN_ONE.
+    // Stingers play at full volume over the fade out.
+    fn current_gain(&self) -> i64 {
+        if self.after_stinger.is_some() || self.fade_pos >= self.fade_len {
+            GAIN_ONE
+        } else {
+            GAIN_ONE * self.fade_pos as i64 / self.fade_len as i64
+        }
+    }
+}
+
+fn load<'a>(cue: &Cue<'a>, intensity: u8) -> Result<Playing<'a>, TrackError> {
+    Ok(Playing {
+        cue: *cue,
+        track: Track::from_bytes(cue.bytes, cue.ending)?,
+        layers: Layers::load(cue.layers, cue.ending, intensity)?,
+        position: 0,
+    })
+}
+
+impl FrameSource for Playlist<'_> {
+    fn next_frame(&mut self) -> Frame {
+        let finished = self.current.as_ref().is_some_and(|p| p.track.finished());
+        if finished {
+            if let Some(next) = self.after_stinger.take() {
+                // Already loaded once in change(), so this can't fail
+                self.current = next.and_then(|cue| load(&cue, self.intensity).ok());
+            }
+        }
+
+        let incoming = match &mut self.current {
+            Some(playing) => playing.next_frame(),
+            None => [0, 0],
+        };
+        if self.outgoing.iter().all(Option::is_none) {
+            return incoming;
+        }
+
+        let in_gain = self.current_gain();
+        if self.fade_pos < self.fade_len {
+            self.fade_pos += 1;
+        }
+        let mut mix = [0, 0];
+        for (sum, sample) in mix.iter_mut().zip(incoming) {
+            *sum += sample as i64 * in_gain;
+        }
+        for slot in self.outgoing.iter_mut() {
+            let Some(fading) = slot else {
+                continue;
+            };
+            if fading.pos >= fading.len {
+                *slot = None;
+                continue;
+            }
+            let gain = fading.gain();
+            fading.pos += 1;
+            for (sum, sample) in mix.iter_mut().zip(fading.playing.next_frame()) {
+                *sum += sample as i64 * gain;
+            }
+        }
+        mix.map(|sum| (sum / GAIN_ONE).clamp(i16::MIN as i64, i16::MAX as i64) as i16)
+    }
+}
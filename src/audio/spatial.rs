@@ -0,0 +1,89 @@
+use crate::trig::sqrt;
+
+// Positional audio for sound effects that come from something in the world.
+// The game works out where the source is relative to the listener
+// (usually the player's ship) about once a tick, and we turn that into
+// a left and right gain and a Doppler pitch shift for the voice.
+// This is the only float math in the audio path, and it only runs per tick,
+// never per sample.
+
+// Where a sound is coming from, relative to the listener, in world units.
+// +x is right, +y is up, and +z is straight ahead.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Emitter {
+    pub offset: [f32; 3],
+    // How fast offset is changing, in world units per second
+    pub velocity: [f32; 3],
+}
+
+// How loud a voice is on each side, and how far to shift its pitch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    // Out of GAIN_ONE
+    pub left: u16,
+    pub right: u16,
+    // 16.16 fixed point, multiplies every frequency in the voice
+    pub pitch: u32,
+}
+
+pub const GAIN_ONE: u16 = 256;
+pub const PITCH_ONE: u32 = 1 << 16;
+
+// A sound this far away plays at half volume
+const REFERENCE_DISTANCE: f32 = 20.0;
+// In world units per second. Much lower than it should be,
+// so the towers going past actually sound like something.
+const SPEED_OF_SOUND: f32 = 400.0;
+// Keeps the shift musical even for things moving faster than sound
+const MIN_DOPPLER: f32 = 0.5;
+const MAX_DOPPLER: f32 = 2.0;
+
+impl Placement {
+    // Straight ahead, at full volume
+    pub const CENTER: Self = Self {
+        left: GAIN_ONE,
+        right: GAIN_ONE,
+        pitch: PITCH_ONE,
+    };
+
+    pub fn from_emitter(emitter: &Emitter) -> Self {
+        let [x, y, z] = emitter.offset;
+        let distance_squared = x * x + y * y + z * z;
+
+        // Inverse square falloff, but with no sqrt, and no blowing up up close
+        let reference_squared = REFERENCE_DISTANCE * REFERENCE_DISTANCE;
+        let gain = reference_squared / (reference_squared + distance_squared);
+
+        // -1 hard left to 1 hard right. Height doesn't matter for panning,
+        // and dividing by |x| + |z| instead of the real distance
+        // is close enough for two speakers.
+        let pan = if x == 0.0 {
+            0.0
+        } else {
+            x / (x.abs() + z.abs())
+        };
+        // Balance rather than constant power, so the center stays full volume
+        let left = gain * (1.0 - pan).min(1.0);
+        let right = gain * (1.0 + pan).min(1.0);
+
+        // Positive when the source is coming towards us
+        let distance = sqrt(distance_squared);
+        let approaching = if distance > 0.0 {
+            let [vx, vy, vz] = emitter.velocity;
+            -(vx * x + vy * y + vz * z) / distance
+        } else {
+            0.0
+        };
+        let doppler = if approaching >= SPEED_OF_SOUND {
+            MAX_DOPPLER
+        } else {
+            (SPEED_OF_SOUND / (SPEED_OF_SOUND - approaching)).clamp(MIN_DOPPLER, MAX_DOPPLER)
+        };
+
+        Self {
+            left: (left * GAIN_ONE as f32) as u16,
+            right: (right * GAIN_ONE as f32) as u16,
+            pitch: (doppler * PITCH_ONE as f32) as u32,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use super::{
+    spatial::{Placement, PITCH_ONE},
+    SAMPLE_RATE,
+};
+
+// A tiny sfxr style synthesizer, so sound effects can be a handful of numbers
+// instead of recorded samples. Each effect is one oscillator with a pitch sweep,
+// shaped by an ADSR envelope and run through a one pole low pass filter.
+// Like everything else in the audio path it is integer only.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Saw,
+    Triangle,
+    Noise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sfx {
+    pub waveform: Waveform,
+    // The pitch slides linearly from start_hz to end_hz over the whole effect.
+    // For Noise, this is how often a new random value is picked.
+    pub start_hz: u32,
+    pub end_hz: u32,
+    // Fraction of each Square period spent high, out of 256
+    pub duty: u8,
+    pub attack_ms: u16,
+    pub decay_ms: u16,
+    pub sustain_ms: u16,
+    pub release_ms: u16,
+    // Out of 255, relative to the peak reached after the attack
+    pub sustain_level: u8,
+    // Out of 255
+    pub volume: u8,
+    // 255 passes everything, lower values muffle the sound more
+    pub lowpass: u8,
+}
+
+impl Sfx {
+    fn total_samples(&self) -> u32 {
+        ms_to_samples(self.attack_ms)
+            + ms_to_samples(self.decay_ms)
+            + ms_to_samples(self.sustain_ms)
+            + ms_to_samples(self.release_ms)
+    }
+}
+
+fn ms_to_samples(ms: u16) -> u32 {
+    ms as u32 * SAMPLE_RATE / 1000
+}
+
+// One playing instance of an Sfx
+#[derive(Debug, Clone, Copy)]
+pub struct Voice {
+    sfx: Sfx,
+    // How many samples we have played so far
+    elapsed: u32,
+    total: u32,
+    // Oscillator phase, a full period is 2^32
+    phase: u32,
+    noise: u16,
+    noise_value: i32,
+    filtered: i32,
+    placement: Placement,
+}
+
+// Envelope levels are 16 bit fractions
+const ENVELOPE_ONE: u32 = 1 << 16;
+
+impl Voice {
+    pub fn new(sfx: &Sfx) -> Self {
+        Self {
+            sfx: *sfx,
+            elapsed: 0,
+            total: sfx.total_samples(),
+            phase: 0,
+            // any nonzero seed works for the LFSR
+            noise: 0xACE1,
+            noise_value: 0,
+            filtered: 0,
+            placement: Placement::CENTER,
+        }
+    }
+
+    pub fn placement(&self) -> Placement {
+        self.placement
+    }
+
+    pub fn set_placement(&mut self, placement: Placement) {
+        self.placement = placement;
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= self.total
+    }
+
+    pub fn elapsed(&self) -> u32 {
+        self.elapsed
+    }
+
+    fn frequency(&self) -> u32 {
+        let start = self.sfx.start_hz as i64;
+        let end = self.sfx.end_hz as i64;
+        let total = self.total.max(1) as i64;
+        let hz = start + (end - start) * self.elapsed as i64 / total;
+        (hz * self.placement.pitch as i64 / PITCH_ONE as i64) as u32
+    }
+
+    // 0..=ENVELOPE_ONE
+    fn envelope(&self) -> u32 {
+        let attack = ms_to_samples(self.sfx.attack_ms) as u64;
+        let decay = ms_to_samples(self.sfx.decay_ms) as u64;
+        let sustain = ms_to_samples(self.sfx.sustain_ms) as u64;
+        let release = ms_to_samples(self.sfx.release_ms) as u64;
+        let one = ENVELOPE_ONE as u64;
+        let sustain_level = self.sfx.sustain_level as u64 * one / 255;
+
+        // u64 so long stages can't overflow
+        let mut t = self.elapsed as u64;
+        let level = if t < attack {
+            t * one / attack
+        } else {
+            t -= attack;
+            if t < decay {
+                one - (one - sustain_level) * t / decay
+            } else {
+                t -= decay;
+                if t < sustain {
+                    sustain_level
+                } else {
+                    t -= sustain;
+                    if t < release {
+                        sustain_level - sustain_level * t / release
+                    } else {
+                        0
+                    }
+                }
+            }
+        };
+        level as u32
+    }
+
+    // Raw oscillator output, -32768..=32767
+    fn oscillator(&mut self) -> i32 {
+        let phase = self.phase;
+        match self.sfx.waveform {
+            Waveform::Square => {
+                if (phase >> 24) < self.sfx.duty as u32 {
+                    i16::MAX as i32
+                } else {
+                    i16::MIN as i32
+                }
+            }
+            Waveform::Saw => (phase >> 16) as i32 - 32768,
+            Waveform::Triangle => {
+                // fold the saw in half, then stretch it back to full range
+                let saw = (phase >> 15) as i32;
+                let folded = if saw < 65536 { saw } else { 131071 - saw };
+                folded - 32768
+            }
+            Waveform::Noise => self.noise_value,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> i16 {
+        if self.finished() {
+            return 0;
+        }
+
+        let raw = self.oscillator();
+
+        let step = ((self.frequency() as u64) << 32) / SAMPLE_RATE as u64;
+        let (phase, wrapped) = self.phase.overflowing_add(step as u32);
+        self.phase = phase;
+        if wrapped && self.sfx.waveform == Waveform::Noise {
+            // 16 bit Galois LFSR
+            let lsb = self.noise & 1;
+            self.noise >>= 1;
+            if lsb != 0 {
+                self.noise ^= 0xB400;
+            }
+            self.noise_value = self.noise as i16 as i32;
+        }
+
+        self.filtered += (raw - self.filtered) * (self.sfx.lowpass as i32 + 1) / 256;
+
+        let amplitude = self.filtered as i64 * self.envelope() as i64 / ENVELOPE_ONE as i64;
+        let out = amplitude * self.sfx.volume as i64 / 255;
+
+        self.elapsed += 1;
+        out as i16
+    }
+}
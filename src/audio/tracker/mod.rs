@@ -0,0 +1,190 @@
+pub mod player;
+
+// A no_std reader for ProTracker .mod files (and the 6/8/xx channel variants
+// from FastTracker and friends), so we can ship music as patterns and small
+// samples instead of minutes of PCM.
+// I worked from https://www.aes.id.au/modformat.html
+// and the "Noisetracker/Soundtracker/Protracker Module Format" docs.
+// FastTracker .xm files are a different beast (instruments, envelopes,
+// packed patterns) and are not supported.
+// Nothing is copied, the parsed Module just points into the original bytes.
+
+const NUM_SAMPLES: usize = 31;
+const SAMPLE_HEADER_SIZE: usize = 30;
+const SAMPLE_HEADERS_AT: usize = 20;
+const SONG_LENGTH_AT: usize = 950;
+const RESTART_AT: usize = 951;
+const ORDERS_AT: usize = 952;
+const NUM_ORDERS: usize = 128;
+const SIGNATURE_AT: usize = 1080;
+const PATTERNS_AT: usize = 1084;
+
+pub const ROWS_PER_PATTERN: usize = 64;
+const BYTES_PER_CELL: usize = 4;
+pub const MAX_CHANNELS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModError {
+    TooShort,
+    // Also what the old 15 sample Soundtracker files get, since they have no signature
+    UnknownSignature([u8; 4]),
+    BadSongLength(u8),
+    // The patterns run past the end of the file
+    Truncated,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SampleHeader<'a> {
+    // signed 8 bit mono
+    pub data: &'a [u8],
+    // -8..=7, in eighths of a semitone
+    pub finetune: i8,
+    // 0..=64
+    pub volume: u8,
+    // in bytes, only meaningful if loop_len > 2
+    pub loop_start: usize,
+    pub loop_len: usize,
+}
+
+impl SampleHeader<'_> {
+    pub fn loops(&self) -> bool {
+        self.loop_len > 2
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cell {
+    // 1-based, 0 means keep the channel's current sample
+    pub sample: u8,
+    // Amiga period, 0 means no new note
+    pub period: u16,
+    pub effect: u8,
+    pub param: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Module<'a> {
+    pub num_channels: usize,
+    pub song_length: usize,
+    pub restart: usize,
+    orders: &'a [u8],
+    patterns: &'a [u8],
+    samples: [SampleHeader<'a>; NUM_SAMPLES],
+}
+
+fn read_u16_be(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn channels_for_signature(sig: &[u8]) -> Option<usize> {
+    match sig {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"6CHN" => Some(6),
+        b"8CHN" | b"OCTA" | b"CD81" | b"FLT8" => Some(8),
+        [d0 @ b'0'..=b'9', d1 @ b'0'..=b'9', b'C', b'H' | b'N'] => {
+            let channels = ((d0 - b'0') * 10 + (d1 - b'0')) as usize;
+            (1..=MAX_CHANNELS).contains(&channels).then_some(channels)
+        }
+        [d @ b'1'..=b'9', b'C', b'H', b'N'] => Some((d - b'0') as usize),
+        _ => None,
+    }
+}
+
+pub fn is_mod(bytes: &[u8]) -> bool {
+    bytes.len() >= PATTERNS_AT
+        && channels_for_signature(&bytes[SIGNATURE_AT..SIGNATURE_AT + 4]).is_some()
+}
+
+impl<'a> Module<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ModError> {
+        if bytes.len() < PATTERNS_AT {
+            return Err(ModError::TooShort);
+        }
+
+        let sig = &bytes[SIGNATURE_AT..SIGNATURE_AT + 4];
+        let num_channels = channels_for_signature(sig)
+            .ok_or(ModError::UnknownSignature([sig[0], sig[1], sig[2], sig[3]]))?;
+
+        let song_length = bytes[SONG_LENGTH_AT];
+        if song_length == 0 || song_length as usize > NUM_ORDERS {
+            return Err(ModError::BadSongLength(song_length));
+        }
+        let song_length = song_length as usize;
+
+        // Lots of trackers put 127 or garbage here, so anything
+        // out of range just means start over
+        let restart = bytes[RESTART_AT] as usize;
+        let restart = if restart < song_length { restart } else { 0 };
+
+        let orders = &bytes[ORDERS_AT..ORDERS_AT + NUM_ORDERS];
+        // Patterns are stored up to the highest one mentioned anywhere in the
+        // order table, even past song_length
+        let num_patterns = *orders.iter().max().unwrap() as usize + 1;
+        let pattern_bytes = num_patterns * ROWS_PER_PATTERN * num_channels * BYTES_PER_CELL;
+        let patterns_end = PATTERNS_AT + pattern_bytes;
+        if patterns_end > bytes.len() {
+            return Err(ModError::Truncated);
+        }
+        let patterns = &bytes[PATTERNS_AT..patterns_end];
+
+        let mut samples = [SampleHeader::default(); NUM_SAMPLES];
+        let mut data_at = patterns_end;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let header = SAMPLE_HEADERS_AT + i * SAMPLE_HEADER_SIZE;
+            // lengths are all in 16 bit words
+            let len = read_u16_be(bytes, header + 22) as usize * 2;
+            let finetune = bytes[header + 24] & 0x0F;
+            let volume = bytes[header + 25].min(64);
+            let loop_start = read_u16_be(bytes, header + 26) as usize * 2;
+            let loop_len = read_u16_be(bytes, header + 28) as usize * 2;
+
+            // Plenty of mods in the wild are missing the end of their last sample
+            let start = data_at.min(bytes.len());
+            let end = (data_at + len).min(bytes.len());
+            let data = &bytes[start..end];
+            data_at += len;
+
+            let loop_start = loop_start.min(data.len());
+            let loop_len = loop_len.min(data.len() - loop_start);
+
+            *sample = SampleHeader {
+                data,
+                // sign extend the low nibble
+                finetune: ((finetune << 4) as i8) >> 4,
+                volume,
+                loop_start,
+                loop_len,
+            };
+        }
+
+        Ok(Self {
+            num_channels,
+            song_length,
+            restart,
+            orders,
+            patterns,
+            samples,
+        })
+    }
+
+    pub fn pattern_at(&self, order: usize) -> usize {
+        self.orders[order] as usize
+    }
+
+    pub fn cell(&self, pattern: usize, row: usize, channel: usize) -> Cell {
+        let at =
+            ((pattern * ROWS_PER_PATTERN + row) * self.num_channels + channel) * BYTES_PER_CELL;
+        let b = &self.patterns[at..at + BYTES_PER_CELL];
+        Cell {
+            sample: (b[0] & 0xF0) | (b[2] >> 4),
+            period: (((b[0] & 0x0F) as u16) << 8) | b[1] as u16,
+            effect: b[2] & 0x0F,
+            param: b[3],
+        }
+    }
+
+    // sample is 1-based, like in the patterns
+    pub fn sample(&self, sample: u8) -> Option<&SampleHeader<'a>> {
+        self.samples.get((sample as usize).checked_sub(1)?)
+    }
+}
@@ -0,0 +1,409 @@
+use crate::audio::{Ending, Frame, FrameSource, SAMPLE_RATE};
+
+use super::{Cell, Module, MAX_CHANNELS, ROWS_PER_PATTERN};
+
+// Renders a Module one frame at a time, looping the song or stopping at its end.
+// Everything is integer math, since floats are done in software on our target.
+//
+// Timing works like ProTracker: every row lasts 'speed' ticks, and a tick
+// lasts 2.5 / bpm seconds. Notes are triggered on the first tick of a row,
+// and effects like slides and vibrato update on every tick after that.
+
+// The Amiga's PAL clock divided by 2, i.e. period 1 plays at this many Hz
+const AMIGA_CLOCK: u64 = 3_546_895;
+// So far apart that slides can't get stuck outside of it
+const MIN_PERIOD: u16 = 28;
+const MAX_PERIOD: u16 = 3424;
+
+const DEFAULT_SPEED: u8 = 6;
+const DEFAULT_BPM: u8 = 125;
+
+// Sample positions are 48.16 fixed point
+const FRACTION_BITS: u32 = 16;
+
+// 2^(finetune / 96) in 16.16 fixed point, for finetune -8..=7
+const FINETUNE: [u64; 16] = [
+    61858, 62306, 62757, 63212, 63670, 64132, 64596, 65065, 65536, 66011, 66489, 66971, 67456,
+    67945, 68438, 68933,
+];
+// 2^(semitones / 12) in 16.16 fixed point, for arpeggio
+const SEMITONES: [u64; 16] = [
+    65536, 69433, 73562, 77936, 82570, 87480, 92682, 98193, 104032, 110218, 116772, 123715, 131072,
+    138866, 147123, 155872,
+];
+// ProTracker's own vibrato table, half of a sine wave
+const VIBRATO: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+// Effect numbers, see the format docs for what the parameters mean
+const ARPEGGIO: u8 = 0x0;
+const PORTA_UP: u8 = 0x1;
+const PORTA_DOWN: u8 = 0x2;
+const TONE_PORTA: u8 = 0x3;
+const VIBRATO_EFFECT: u8 = 0x4;
+const TONE_PORTA_VOLUME_SLIDE: u8 = 0x5;
+const VIBRATO_VOLUME_SLIDE: u8 = 0x6;
+const SAMPLE_OFFSET: u8 = 0x9;
+const VOLUME_SLIDE: u8 = 0xA;
+const POSITION_JUMP: u8 = 0xB;
+const SET_VOLUME: u8 = 0xC;
+const PATTERN_BREAK: u8 = 0xD;
+const EXTENDED: u8 = 0xE;
+const SET_SPEED: u8 = 0xF;
+// Extended effects, in the high nibble of the parameter
+const FINE_PORTA_UP: u8 = 0x1;
+const FINE_PORTA_DOWN: u8 = 0x2;
+const FINE_VOLUME_UP: u8 = 0xA;
+const FINE_VOLUME_DOWN: u8 = 0xB;
+const NOTE_CUT: u8 = 0xC;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Channel {
+    // 1-based, 0 means nothing has played yet
+    sample: u8,
+    playing: bool,
+    position: u64,
+    // How far position moves each frame, worked out once per tick
+    step: u64,
+
+    // The period notes and slides change
+    period: u16,
+    // The period actually played this tick, after vibrato
+    out_period: u16,
+    arpeggio: u8,
+    finetune: i8,
+    volume: u8,
+
+    effect: u8,
+    param: u8,
+    // Effects with a parameter of 0 reuse the last one
+    porta_speed: u8,
+    target_period: u16,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_pos: u8,
+}
+
+pub struct ModPlayer<'a> {
+    module: Module<'a>,
+    channels: [Channel; MAX_CHANNELS],
+    order: usize,
+    row: usize,
+    tick: u8,
+    speed: u8,
+    bpm: u8,
+    frames_left_in_tick: u32,
+    // Set by position jumps and pattern breaks, applied at the end of the row
+    jump_to: Option<(usize, usize)>,
+    // Modules loop back to their own restart position, so any
+    // Ending::LoopFrom frame is ignored
+    ending: Ending,
+    finished: bool,
+}
+
+impl<'a> ModPlayer<'a> {
+    pub fn new(module: Module<'a>, ending: Ending) -> Self {
+        Self {
+            module,
+            channels: [Channel::default(); MAX_CHANNELS],
+            order: 0,
+            row: 0,
+            tick: 0,
+            speed: DEFAULT_SPEED,
+            bpm: DEFAULT_BPM,
+            frames_left_in_tick: 0,
+            jump_to: None,
+            ending,
+            finished: false,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    fn frames_per_tick(&self) -> u32 {
+        // 2.5 seconds / bpm
+        SAMPLE_RATE * 5 / (2 * self.bpm as u32)
+    }
+
+    fn process_tick(&mut self) {
+        if self.tick == 0 {
+            self.play_row();
+        } else {
+            for c in 0..self.module.num_channels {
+                self.update_effect(c);
+            }
+        }
+
+        for channel in &mut self.channels[..self.module.num_channels] {
+            channel.step = step_for(channel);
+        }
+
+        self.tick += 1;
+        if self.tick >= self.speed {
+            self.tick = 0;
+            self.next_row();
+        }
+    }
+
+    fn next_row(&mut self) {
+        let (order, row) = match self.jump_to.take() {
+            Some(jump) => jump,
+            None => (self.order, self.row + 1),
+        };
+        self.order = order;
+        self.row = row;
+
+        if self.row >= ROWS_PER_PATTERN {
+            self.row = 0;
+            self.order += 1;
+        }
+        if self.order >= self.module.song_length {
+            match self.ending {
+                Ending::LoopFrom(_) => self.order = self.module.restart,
+                Ending::Stop => {
+                    self.order = 0;
+                    self.finished = true;
+                }
+            }
+        }
+    }
+
+    fn play_row(&mut self) {
+        let pattern = self.module.pattern_at(self.order);
+        for c in 0..self.module.num_channels {
+            let cell = self.module.cell(pattern, self.row, c);
+            self.trigger(c, cell);
+        }
+    }
+
+    // Everything that happens on the first tick of a row
+    fn trigger(&mut self, c: usize, cell: Cell) {
+        let channel = &mut self.channels[c];
+        channel.effect = cell.effect;
+        channel.param = cell.param;
+        channel.arpeggio = 0;
+
+        if let Some(sample) = self.module.sample(cell.sample) {
+            channel.sample = cell.sample;
+            channel.volume = sample.volume;
+            channel.finetune = sample.finetune;
+        }
+
+        let is_tone_porta = matches!(cell.effect, TONE_PORTA | TONE_PORTA_VOLUME_SLIDE);
+        if cell.period != 0 {
+            if is_tone_porta {
+                // slide to the new note instead of playing it
+                channel.target_period = cell.period;
+            } else {
+                channel.period = cell.period;
+                channel.position = 0;
+                channel.playing = channel.sample != 0;
+                channel.vibrato_pos = 0;
+            }
+        }
+
+        let (x, y) = (cell.param >> 4, cell.param & 0x0F);
+        match cell.effect {
+            TONE_PORTA if cell.param != 0 => channel.porta_speed = cell.param,
+            VIBRATO_EFFECT => {
+                if x != 0 {
+                    channel.vibrato_speed = x;
+                }
+                if y != 0 {
+                    channel.vibrato_depth = y;
+                }
+            }
+            SAMPLE_OFFSET if cell.period != 0 => {
+                channel.position = ((cell.param as u64) << 8) << FRACTION_BITS;
+            }
+            SET_VOLUME => channel.volume = cell.param.min(64),
+            POSITION_JUMP => {
+                self.jump_to = Some((cell.param as usize, 0));
+            }
+            PATTERN_BREAK => {
+                // the row is written in decimal, for some reason
+                let row = (x * 10 + y) as usize;
+                let order = match self.jump_to {
+                    Some((order, _)) => order,
+                    None => self.order + 1,
+                };
+                self.jump_to = Some((order, row.min(ROWS_PER_PATTERN - 1)));
+            }
+            EXTENDED => match x {
+                FINE_PORTA_UP => {
+                    channel.period = clamp_period(channel.period.saturating_sub(y as u16))
+                }
+                FINE_PORTA_DOWN => channel.period = clamp_period(channel.period + y as u16),
+                FINE_VOLUME_UP => channel.volume = (channel.volume + y).min(64),
+                FINE_VOLUME_DOWN => channel.volume = channel.volume.saturating_sub(y),
+                NOTE_CUT if y == 0 => channel.volume = 0,
+                _ => {}
+            },
+            SET_SPEED => match cell.param {
+                0 => {}
+                1..=31 => self.speed = cell.param,
+                _ => self.bpm = cell.param,
+            },
+            _ => {}
+        }
+        let channel = &mut self.channels[c];
+        channel.out_period = channel.period;
+    }
+
+    // Everything that happens on the ticks after the first
+    fn update_effect(&mut self, c: usize) {
+        let tick = self.tick;
+        let channel = &mut self.channels[c];
+        let (x, y) = (channel.param >> 4, channel.param & 0x0F);
+        channel.out_period = channel.period;
+
+        match channel.effect {
+            ARPEGGIO if channel.param != 0 => {
+                channel.arpeggio = match tick % 3 {
+                    0 => 0,
+                    1 => x,
+                    _ => y,
+                };
+            }
+            PORTA_UP => {
+                channel.period = clamp_period(channel.period.saturating_sub(channel.param as u16));
+                channel.out_period = channel.period;
+            }
+            PORTA_DOWN => {
+                channel.period = clamp_period(channel.period + channel.param as u16);
+                channel.out_period = channel.period;
+            }
+            TONE_PORTA => tone_porta(channel),
+            TONE_PORTA_VOLUME_SLIDE => {
+                tone_porta(channel);
+                volume_slide(channel, x, y);
+            }
+            VIBRATO_EFFECT => vibrato(channel),
+            VIBRATO_VOLUME_SLIDE => {
+                vibrato(channel);
+                volume_slide(channel, x, y);
+            }
+            VOLUME_SLIDE => volume_slide(channel, x, y),
+            EXTENDED if x == NOTE_CUT && y == tick => channel.volume = 0,
+            _ => {}
+        }
+    }
+
+    fn mix_frame(&mut self) -> Frame {
+        let mut left = 0i32;
+        let mut right = 0i32;
+
+        for (c, channel) in self.channels[..self.module.num_channels]
+            .iter_mut()
+            .enumerate()
+        {
+            if !channel.playing {
+                continue;
+            }
+            let Some(sample) = self.module.sample(channel.sample) else {
+                continue;
+            };
+
+            let mut index = (channel.position >> FRACTION_BITS) as usize;
+            if sample.loops() {
+                let loop_end = sample.loop_start + sample.loop_len;
+                if index >= loop_end {
+                    index = sample.loop_start + (index - sample.loop_start) % sample.loop_len;
+                    let frac = channel.position & ((1 << FRACTION_BITS) - 1);
+                    channel.position = ((index as u64) << FRACTION_BITS) | frac;
+                }
+            } else if index >= sample.data.len() {
+                channel.playing = false;
+                continue;
+            }
+
+            // 8 bit sample * 0..=64 volume fits comfortably in 14 bits
+            let value = sample.data[index] as i8 as i32 * channel.volume as i32;
+            channel.position += channel.step;
+
+            // The Amiga hard pans its channels left, right, right, left.
+            // Full separation sounds odd on headphones, so we bleed a quarter across.
+            if matches!(c % 4, 0 | 3) {
+                left += value * 3;
+                right += value;
+            } else {
+                left += value;
+                right += value * 3;
+            }
+        }
+
+        // Each channel contributes at most 4 * 2^13 between both sides,
+        // so with four channels this just reaches full scale.
+        let num_channels = self.module.num_channels as i32;
+        let scale = |x: i32| (x * 4 / num_channels).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        [scale(left), scale(right)]
+    }
+}
+
+impl FrameSource for ModPlayer<'_> {
+    fn next_frame(&mut self) -> Frame {
+        if self.finished {
+            return [0, 0];
+        }
+        if self.frames_left_in_tick == 0 {
+            self.process_tick();
+            self.frames_left_in_tick = self.frames_per_tick();
+        }
+        self.frames_left_in_tick -= 1;
+
+        self.mix_frame()
+    }
+}
+
+fn clamp_period(period: u16) -> u16 {
+    period.clamp(MIN_PERIOD, MAX_PERIOD)
+}
+
+fn step_for(channel: &Channel) -> u64 {
+    if channel.out_period == 0 {
+        return 0;
+    }
+    let base = (AMIGA_CLOCK << FRACTION_BITS) / (channel.out_period as u64 * SAMPLE_RATE as u64);
+    let finetuned = (base * FINETUNE[(channel.finetune + 8) as usize]) >> 16;
+    (finetuned * SEMITONES[channel.arpeggio as usize]) >> 16
+}
+
+fn tone_porta(channel: &mut Channel) {
+    let speed = channel.porta_speed as u16;
+    if channel.target_period == 0 {
+        return;
+    }
+    if channel.period < channel.target_period {
+        channel.period = (channel.period + speed).min(channel.target_period);
+    } else {
+        channel.period = channel
+            .period
+            .saturating_sub(speed)
+            .max(channel.target_period);
+    }
+    channel.out_period = channel.period;
+}
+
+fn vibrato(channel: &mut Channel) {
+    let pos = channel.vibrato_pos;
+    let delta = (VIBRATO[(pos & 31) as usize] as u16 * channel.vibrato_depth as u16) >> 7;
+    channel.out_period = if pos & 32 == 0 {
+        clamp_period(channel.period + delta)
+    } else {
+        clamp_period(channel.period.saturating_sub(delta))
+    };
+    channel.vibrato_pos = (pos + channel.vibrato_speed) & 63;
+}
+
+// Only one direction at a time, up wins if both are given
+fn volume_slide(channel: &mut Channel, up: u8, down: u8) {
+    if up != 0 {
+        channel.volume = (channel.volume + up).min(64);
+    } else {
+        channel.volume = channel.volume.saturating_sub(down);
+    }
+}
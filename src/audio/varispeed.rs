@@ -0,0 +1,69 @@
+use super::{effects::Param, Frame, FrameSource};
+
+// Plays the music faster or slower, like a tape or record deck with
+// a pitch slider: the tempo and the pitch go up and down together.
+// It pulls frames from the music as fast as the rate says, and
+// interpolates between the last two for each frame it puts out.
+// The rate glides rather than jumps, so changing it never clicks,
+// and gliding it down a long way makes a nice tape stop.
+
+// Rates are 16.16 fixed point
+const FRACTION_BITS: u32 = 16;
+const ONE: u32 = 1 << FRACTION_BITS;
+
+// Below this it's a drone, above it's chipmunks
+const MIN_RATE: f32 = 0.25;
+const MAX_RATE: f32 = 4.0;
+
+pub struct Varispeed {
+    rate: Param,
+    // Output is between these two, 'frac' of the way to next
+    prev: Frame,
+    next: Frame,
+    frac: u32,
+}
+
+impl Varispeed {
+    pub const fn new() -> Self {
+        Self {
+            rate: Param::new(ONE as i32),
+            prev: [0, 0],
+            next: [0, 0],
+            frac: 0,
+        }
+    }
+
+    // 1.0 is normal speed, 2.0 is twice as fast and an octave up
+    pub fn set_rate(&mut self, rate: f32, ms: u32) {
+        let rate = rate.clamp(MIN_RATE, MAX_RATE);
+        self.rate.set((rate * ONE as f32) as i32, ms);
+    }
+
+    // How many source frames go by in this many output frames, at the current rate
+    pub fn source_frames(&self, frames: u32) -> u32 {
+        ((frames as u64 * self.rate.get() as u64) >> FRACTION_BITS) as u32
+    }
+
+    pub fn next_frame(&mut self, source: &mut dyn FrameSource) -> Frame {
+        self.rate.tick();
+
+        // Linear interpolation, like wav::WavSource. It dulls the highs a
+        // little when slowed right down, but the music is lo-fi anyway.
+        let frac = self.frac as i32;
+        let lerp =
+            |a: i16, b: i16| (a as i32 + (((b as i32 - a as i32) * frac) >> FRACTION_BITS)) as i16;
+        let out = [
+            lerp(self.prev[0], self.next[0]),
+            lerp(self.prev[1], self.next[1]),
+        ];
+
+        self.frac += self.rate.get() as u32;
+        while self.frac >= ONE {
+            self.frac -= ONE;
+            self.prev = self.next;
+            self.next = source.next_frame();
+        }
+
+        out
+    }
+}
@@ -0,0 +1,324 @@
+use super::{Ending, Frame, FrameSource, SAMPLE_RATE};
+
+// A no_std reader for the kind of .wav files audio tools export:
+// uncompressed PCM, 8/16/24 bit, any number of channels, any sample rate,
+// and IMA ADPCM (mono or stereo), which audio::adpcm decodes.
+// See http://soundfile.sapp.org/doc/WaveFormat/
+// and https://www.mmsp.ece.mcgill.ca/Documents/AudioFormats/WAVE/WAVE.html
+// Nothing is copied, the parsed Wav just points into the original bytes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavError {
+    NotRiff,
+    NotWave,
+    // A chunk header says it is longer than the file
+    Truncated,
+    MissingFmt,
+    MissingData,
+    // Only 1 (PCM) and 0x11 (IMA ADPCM) are supported,
+    // or 0xFFFE (extensible) wrapping one of them
+    UnsupportedFormat(u16),
+    UnsupportedBitDepth(u16),
+    NoChannels,
+    // IMA ADPCM can only be mono or stereo
+    UnsupportedChannels(u16),
+    BadSampleRate(u32),
+    // An IMA ADPCM block too small to hold its own header
+    BadBlockAlign(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Pcm,
+    // Each block holds this many frames, the first of which is in its header
+    ImaAdpcm { frames_per_block: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Wav<'a> {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub encoding: Encoding,
+    block_align: usize,
+    data: &'a [u8],
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Every IMA ADPCM block starts with a 16 bit sample,
+// an 8 bit step index, and a padding byte, for each channel
+pub const IMA_HEADER_BYTES_PER_CHANNEL: usize = 4;
+pub const IMA_GROUP_BYTES: usize = 4;
+pub const IMA_SAMPLES_PER_GROUP: usize = 8;
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+// The header holds the first frame, and after it the channels take turns
+// with 4 bytes (8 samples) at a time
+fn ima_frames_in(block_bytes: usize, channels: u16) -> usize {
+    let header_bytes = IMA_HEADER_BYTES_PER_CHANNEL * channels as usize;
+    let group_bytes = IMA_GROUP_BYTES * channels as usize;
+    1 + (block_bytes - header_bytes) / group_bytes * IMA_SAMPLES_PER_GROUP
+}
+
+pub fn is_riff(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && &bytes[0..4] == b"RIFF"
+}
+
+impl<'a> Wav<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, WavError> {
+        if !is_riff(bytes) || bytes.len() < 12 {
+            return Err(WavError::NotRiff);
+        }
+        if &bytes[8..12] != b"WAVE" {
+            return Err(WavError::NotWave);
+        }
+
+        let mut fmt = None;
+        let mut data = None;
+
+        // Chunks can come in any order, and there may be others (LIST, fact, ...)
+        // that we don't care about
+        let mut at = 12;
+        while at + 8 <= bytes.len() {
+            let id = &bytes[at..at + 4];
+            let len = read_u32(bytes, at + 4) as usize;
+            let body_start = at + 8;
+            let body_end = body_start.checked_add(len).ok_or(WavError::Truncated)?;
+
+            if body_end > bytes.len() {
+                // Some encoders write a bogus length for a data chunk that runs
+                // to the end of the file, which we can still play
+                if id == b"data" {
+                    data = Some(&bytes[body_start..]);
+                    break;
+                }
+                return Err(WavError::Truncated);
+            }
+
+            let body = &bytes[body_start..body_end];
+            match id {
+                b"fmt " => fmt = Some(body),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+
+            // chunks are padded to an even length
+            at = body_end + (len & 1);
+        }
+
+        let fmt = fmt.ok_or(WavError::MissingFmt)?;
+        let data = data.ok_or(WavError::MissingData)?;
+
+        if fmt.len() < 16 {
+            return Err(WavError::Truncated);
+        }
+        let mut format = read_u16(fmt, 0);
+        let channels = read_u16(fmt, 2);
+        let sample_rate = read_u32(fmt, 4);
+        let bits_per_sample = read_u16(fmt, 14);
+
+        // The extensible header tacks a GUID on the end,
+        // the first two bytes of which are the real format
+        if format == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+            format = read_u16(fmt, 24);
+        }
+
+        if channels == 0 {
+            return Err(WavError::NoChannels);
+        }
+        if sample_rate == 0 {
+            return Err(WavError::BadSampleRate(sample_rate));
+        }
+
+        match format {
+            WAVE_FORMAT_PCM => {
+                if !matches!(bits_per_sample, 8 | 16 | 24) {
+                    return Err(WavError::UnsupportedBitDepth(bits_per_sample));
+                }
+                Ok(Self {
+                    channels,
+                    sample_rate,
+                    bits_per_sample,
+                    encoding: Encoding::Pcm,
+                    // Don't trust the block align field, plenty of files get it wrong
+                    block_align: channels as usize * bits_per_sample as usize / 8,
+                    data,
+                })
+            }
+            WAVE_FORMAT_IMA_ADPCM => {
+                if bits_per_sample != 4 {
+                    return Err(WavError::UnsupportedBitDepth(bits_per_sample));
+                }
+                if channels > 2 {
+                    return Err(WavError::UnsupportedChannels(channels));
+                }
+                // Here the block align is the only way to know where blocks start,
+                // so it has to be right. The frames per block that comes after
+                // it is just this worked out again.
+                let block_align = read_u16(fmt, 12);
+                let header_bytes = IMA_HEADER_BYTES_PER_CHANNEL * channels as usize;
+                if (block_align as usize) <= header_bytes {
+                    return Err(WavError::BadBlockAlign(block_align));
+                }
+                let frames_per_block = ima_frames_in(block_align as usize, channels);
+                Ok(Self {
+                    channels,
+                    sample_rate,
+                    bits_per_sample,
+                    encoding: Encoding::ImaAdpcm { frames_per_block },
+                    block_align: block_align as usize,
+                    data,
+                })
+            }
+            _ => Err(WavError::UnsupportedFormat(format)),
+        }
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    // Bytes per frame for PCM, bytes per block for IMA ADPCM
+    pub fn block_align(&self) -> usize {
+        self.block_align
+    }
+
+    pub fn frames(&self) -> usize {
+        match self.encoding {
+            Encoding::Pcm => self.data.len() / self.block_align,
+            Encoding::ImaAdpcm { frames_per_block } => {
+                let whole_blocks = self.data.len() / self.block_align;
+                // The last block is usually cut short
+                let rest = self.data.len() % self.block_align;
+                let header_bytes = IMA_HEADER_BYTES_PER_CHANNEL * self.channels as usize;
+                let partial = if rest > header_bytes {
+                    ima_frames_in(rest, self.channels)
+                } else {
+                    0
+                };
+                whole_blocks * frames_per_block + partial
+            }
+        }
+    }
+
+    // One channel of one frame, converted to signed 16 bit.
+    // Only for PCM, ADPCM can only be decoded in order.
+    fn sample(&self, frame: usize, channel: u16) -> i16 {
+        debug_assert_eq!(self.encoding, Encoding::Pcm);
+        let bytes_per_sample = self.bits_per_sample as usize / 8;
+        let at = frame * self.block_align + channel as usize * bytes_per_sample;
+        match self.bits_per_sample {
+            // 8 bit wavs are unsigned, centered on 128
+            8 => ((self.data[at] as i16) - 128) << 8,
+            16 => read_u16(self.data, at) as i16,
+            // just drop the lowest byte
+            24 => read_u16(self.data, at + 1) as i16,
+            _ => unreachable!(),
+        }
+    }
+
+    // Mono is copied to both sides, and for anything with more than
+    // two channels we keep front left and right, which always come first
+    pub fn frame(&self, frame: usize) -> Frame {
+        let left = self.sample(frame, 0);
+        let right = if self.channels == 1 {
+            left
+        } else {
+            self.sample(frame, 1)
+        };
+        [left, right]
+    }
+}
+
+// Plays a Wav at SAMPLE_RATE, whatever rate it was recorded at.
+pub struct WavSource<'a> {
+    wav: Wav<'a>,
+    ending: Ending,
+    finished: bool,
+    // Position in the wav, in frames, as 48.16 fixed point
+    position: u64,
+    // How far position moves for every frame we output
+    step: u64,
+}
+
+const FRACTION_BITS: u32 = 16;
+const FRACTION_MASK: u64 = (1 << FRACTION_BITS) - 1;
+
+impl<'a> WavSource<'a> {
+    pub fn new(wav: Wav<'a>, ending: Ending) -> Self {
+        Self {
+            wav,
+            ending,
+            finished: false,
+            position: 0,
+            step: ((wav.sample_rate as u64) << FRACTION_BITS) / SAMPLE_RATE as u64,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    // Where playback continues after the last frame
+    fn loop_start(&self) -> Option<usize> {
+        match self.ending {
+            Ending::LoopFrom(frame) => Some(frame.min(self.wav.frames() - 1)),
+            Ending::Stop => None,
+        }
+    }
+}
+
+impl FrameSource for WavSource<'_> {
+    fn next_frame(&mut self) -> Frame {
+        let frames = self.wav.frames();
+        if self.finished || frames == 0 {
+            return [0, 0];
+        }
+
+        // Only a step longer than the whole loop could take us past the end
+        let index = ((self.position >> FRACTION_BITS) as usize).min(frames - 1);
+        let frac = (self.position & FRACTION_MASK) as i32;
+
+        let out = if frac == 0 {
+            // Always the case when the rates match
+            self.wav.frame(index)
+        } else {
+            // Linear interpolation between neighbouring frames is plenty
+            // for the kinds of rates people actually export
+            let a = self.wav.frame(index);
+            let next = if index + 1 < frames {
+                index + 1
+            } else {
+                self.loop_start().unwrap_or(index)
+            };
+            let b = self.wav.frame(next);
+            let lerp = |a: i16, b: i16| {
+                (a as i32 + (((b as i32 - a as i32) * frac) >> FRACTION_BITS)) as i16
+            };
+            [lerp(a[0], b[0]), lerp(a[1], b[1])]
+        };
+
+        self.position += self.step;
+        let end = (frames as u64) << FRACTION_BITS;
+        if self.position >= end {
+            match self.loop_start() {
+                Some(frame) => {
+                    self.position = self.position - end + ((frame as u64) << FRACTION_BITS)
+                }
+                None => self.finished = true,
+            }
+        }
+
+        out
+    }
+}
@@ -0,0 +1,104 @@
+use x86_64::instructions::port::Port;
+
+// The 8237 DMA controllers, see https://wiki.osdev.org/ISA_DMA
+// We only need the second (16 bit) controller, which owns channels 4-7.
+// Channel 4 is used to cascade the first controller, so only 5-7 are usable.
+
+// indexed by channel - 4
+const ADDRESS_PORTS: [u16; 4] = [0xC0, 0xC4, 0xC8, 0xCC];
+const COUNT_PORTS: [u16; 4] = [0xC2, 0xC6, 0xCA, 0xCE];
+const PAGE_PORTS: [u16; 4] = [0x8F, 0x8B, 0x89, 0x8A];
+
+const SINGLE_CHANNEL_MASK: u16 = 0xD4;
+const MODE: u16 = 0xD6;
+const FLIP_FLOP_RESET: u16 = 0xD8;
+
+// Bits of SINGLE_CHANNEL_MASK, the low two bits select the channel
+const MASK_ON: u8 = 1 << 2;
+
+// Bits of MODE, the low two bits select the channel
+// Bits 2-3=Transfer type, 0b10 reads from memory (i.e. playback)
+// Bit 4=Auto-initialize, start over at the beginning of the buffer when done
+// Bits 6-7=Mode, 0b01 is single transfer which is what sound cards expect
+const TRANSFER_READ: u8 = 0b10 << 2;
+const AUTO_INIT: u8 = 1 << 4;
+const SINGLE_MODE: u8 = 0b01 << 6;
+
+#[derive(Debug)]
+pub struct DmaChannel16 {
+    channel: u8,
+}
+
+impl DmaChannel16 {
+    pub fn new(channel: u8) -> Self {
+        debug_assert!((5..=7).contains(&channel));
+        Self { channel }
+    }
+
+    fn index(&self) -> usize {
+        (self.channel - 4) as usize
+    }
+
+    // Loops over the same buffer forever, until masked.
+    // buf_phys_addr must be below 16MiB and the buffer can't cross a 64KiB boundary,
+    // see PhysAllocator::alloc_isa_dma
+    pub fn begin_auto_init_playback(&self, buf_phys_addr: u32, buf_bytes: u32) {
+        debug_assert!(buf_phys_addr % 2 == 0);
+        debug_assert!(buf_bytes % 2 == 0 && buf_bytes > 0);
+        debug_assert!(buf_phys_addr + buf_bytes <= 16 * 1024 * 1024);
+        debug_assert!(buf_phys_addr >> 16 == (buf_phys_addr + buf_bytes - 1) >> 16);
+
+        self.mask();
+
+        // The 16 bit controller counts in words, not bytes.
+        // The page register still takes bits 16-23 of the byte address,
+        // which is why transfers can't cross a 64KiB (well, 128KiB) boundary.
+        let word_addr = (buf_phys_addr >> 1) & 0xFFFF;
+        let words = buf_bytes / 2 - 1;
+        let page = (buf_phys_addr >> 16) as u8;
+
+        self.write_u16(ADDRESS_PORTS[self.index()], word_addr as u16);
+        self.write_u16(COUNT_PORTS[self.index()], words as u16);
+        unsafe { Port::<u8>::new(PAGE_PORTS[self.index()]).write(page) };
+
+        let mode = TRANSFER_READ | AUTO_INIT | SINGLE_MODE | (self.channel - 4);
+        unsafe { Port::<u8>::new(MODE).write(mode) };
+
+        self.unmask();
+    }
+
+    // How many words are left before the controller wraps back to the start of the buffer
+    pub fn remaining_words(&self) -> u16 {
+        let mut flip_flop = Port::<u8>::new(FLIP_FLOP_RESET);
+        let mut count = Port::<u8>::new(COUNT_PORTS[self.index()]);
+        unsafe {
+            flip_flop.write(0xFF);
+            let low = count.read() as u16;
+            let high = count.read() as u16;
+            // the register holds one less than the number of words left
+            ((high << 8) | low).wrapping_add(1)
+        }
+    }
+
+    pub fn mask(&self) {
+        let mut mask = Port::<u8>::new(SINGLE_CHANNEL_MASK);
+        unsafe { mask.write(MASK_ON | (self.channel - 4)) };
+    }
+
+    fn unmask(&self) {
+        let mut mask = Port::<u8>::new(SINGLE_CHANNEL_MASK);
+        unsafe { mask.write(self.channel - 4) };
+    }
+
+    // The controller has a single flip flop deciding whether the next
+    // byte written is the low or high half, so we reset it first
+    fn write_u16(&self, port: u16, value: u16) {
+        let mut flip_flop = Port::<u8>::new(FLIP_FLOP_RESET);
+        let mut port = Port::<u8>::new(port);
+        unsafe {
+            flip_flop.write(0xFF);
+            port.write((value & 0xFF) as u8);
+            port.write((value >> 8) as u8);
+        }
+    }
+}
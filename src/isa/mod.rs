@@ -0,0 +1,26 @@
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub mod dma;
+pub mod sb16;
+pub mod vga;
+
+// ISA devices can't be enumerated like PCI ones,
+// they sit at well known ports and we just have to poke them and see.
+// Everything in here is for the legacy hardware that QEMU (and old PCs) still provide.
+
+// pluggable_interrupt_os only installs handlers for the timer and keyboard,
+// so if an ISA card raised any other IRQ while it was unmasked we would crash.
+// Drivers that can raise interrupts mask their line and poll instead.
+pub fn mask_irq(irq: u8) {
+    debug_assert!(irq < 16);
+    without_interrupts(|| {
+        let mut pics = pluggable_interrupt_os::interrupts::PICS.lock();
+        let [mut master, mut slave] = unsafe { pics.read_masks() };
+        if irq < 8 {
+            master |= 1 << irq;
+        } else {
+            slave |= 1 << (irq - 8);
+        }
+        unsafe { pics.write_masks(master, slave) };
+    });
+}
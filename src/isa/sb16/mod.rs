@@ -0,0 +1,184 @@
+use x86_64::instructions::port::Port;
+
+use super::{dma::DmaChannel16, mask_irq};
+
+pub mod music_loop;
+
+// I followed https://wiki.osdev.org/Sound_Blaster_16
+// and the "Sound Blaster Series Hardware Programming Guide" from Creative.
+// QEMU's -device sb16 defaults to port 0x220, IRQ 5, DMA 1 and HDMA 5,
+// which are also the factory defaults for real cards.
+
+const DEFAULT_BASE_PORT: u16 = 0x220;
+const DEFAULT_IRQ: u8 = 5;
+const DEFAULT_HIGH_DMA: u8 = 5;
+
+// Rates the DSP can play back at, in Hz.
+// A real SB16 tops out at 44.1kHz, but QEMU happily takes 48kHz,
+// which is what our music is in. On real hardware it will just play a bit slow.
+pub const SAMPLE_RATE: u16 = 48_000;
+
+#[derive(Debug)]
+pub struct Sb16 {
+    base_port: u16,
+    dma: DmaChannel16,
+}
+
+impl Sb16 {
+    // base_port offsets
+    const MIXER_ADDRESS: u16 = 0x4;
+    const MIXER_DATA: u16 = 0x5;
+    const DSP_RESET: u16 = 0x6;
+    const DSP_READ: u16 = 0xA;
+    // Bit 7=DSP busy
+    const DSP_WRITE: u16 = 0xC;
+    // Bit 7=Data available, reading this also acknowledges an 8 bit interrupt
+    const DSP_READ_STATUS: u16 = 0xE;
+    // Reading this acknowledges a 16 bit interrupt
+    const DSP_ACK_16: u16 = 0xF;
+
+    // mixer registers
+    const MIXER_IRQ_SELECT: u8 = 0x80;
+    const MIXER_DMA_SELECT: u8 = 0x81;
+    // Bit 0=8 bit DMA interrupt pending
+    // Bit 1=16 bit DMA interrupt pending
+    const MIXER_IRQ_STATUS: u8 = 0x82;
+
+    // DSP commands
+    const SET_OUTPUT_RATE: u8 = 0x41;
+    const SPEAKER_ON: u8 = 0xD1;
+    // Bit 1=FIFO on
+    // Bit 2=Auto-initialize
+    // Bit 3=Input instead of output
+    // Bits 4-7=0xB for 16 bit transfers
+    const OUTPUT_16_AUTO_INIT: u8 = 0xB6;
+    // Bit 4=Signed
+    // Bit 5=Stereo
+    const FORMAT_SIGNED_STEREO: u8 = 0b0011_0000;
+
+    const RESET_OK: u8 = 0xAA;
+
+    // Resets the DSP at the default port, and if anything answers,
+    // assumes it is a Sound Blaster 16 and returns it.
+    pub fn probe() -> Option<Self> {
+        let me = Self {
+            base_port: DEFAULT_BASE_PORT,
+            dma: DmaChannel16::new(DEFAULT_HIGH_DMA),
+        };
+
+        if me.reset_dsp() {
+            Some(me)
+        } else {
+            None
+        }
+    }
+
+    fn reset_dsp(&self) -> bool {
+        let mut reset = Port::<u8>::new(self.base_port + Self::DSP_RESET);
+        unsafe { reset.write(1) };
+        // The DSP wants 3 microseconds, and each port access takes about one
+        io_delay(4);
+        unsafe { reset.write(0) };
+
+        // The guide says the DSP should answer within 100 microseconds.
+        // If nothing is there, the status port floats high (data available),
+        // but the data port will read 0xFF instead of RESET_OK.
+        for _ in 0..1000 {
+            if self.dsp_data_available() {
+                return self.read_dsp() == Self::RESET_OK;
+            }
+        }
+        false
+    }
+
+    fn dsp_data_available(&self) -> bool {
+        let mut status = Port::<u8>::new(self.base_port + Self::DSP_READ_STATUS);
+        unsafe { status.read() & 0x80 != 0 }
+    }
+
+    fn read_dsp(&self) -> u8 {
+        while !self.dsp_data_available() {}
+        let mut read = Port::<u8>::new(self.base_port + Self::DSP_READ);
+        unsafe { read.read() }
+    }
+
+    fn write_dsp(&self, value: u8) {
+        let mut write = Port::<u8>::new(self.base_port + Self::DSP_WRITE);
+        while unsafe { write.read() } & 0x80 != 0 {}
+        unsafe { write.write(value) };
+    }
+
+    fn write_mixer(&self, register: u8, value: u8) {
+        let mut address = Port::<u8>::new(self.base_port + Self::MIXER_ADDRESS);
+        let mut data = Port::<u8>::new(self.base_port + Self::MIXER_DATA);
+        unsafe {
+            address.write(register);
+            data.write(value);
+        }
+    }
+
+    fn read_mixer(&self, register: u8) -> u8 {
+        let mut address = Port::<u8>::new(self.base_port + Self::MIXER_ADDRESS);
+        let mut data = Port::<u8>::new(self.base_port + Self::MIXER_DATA);
+        unsafe {
+            address.write(register);
+            data.read()
+        }
+    }
+
+    fn init(&self) {
+        self.reset_dsp();
+
+        // We never install a handler for the card's IRQ, see mask_irq
+        mask_irq(DEFAULT_IRQ);
+
+        // Bit 1 of the IRQ select register is IRQ 5,
+        // bit 5 of the DMA select register is DMA 5 (bit 1 keeps DMA 1 for 8 bit)
+        self.write_mixer(Self::MIXER_IRQ_SELECT, 1 << 1);
+        self.write_mixer(Self::MIXER_DMA_SELECT, (1 << 5) | (1 << 1));
+
+        self.write_dsp(Self::SPEAKER_ON);
+
+        self.write_dsp(Self::SET_OUTPUT_RATE);
+        self.write_dsp((SAMPLE_RATE >> 8) as u8);
+        self.write_dsp((SAMPLE_RATE & 0xFF) as u8);
+    }
+
+    // init() must be called first!
+    // buf_phys_addr should come from PhysAllocator::alloc_isa_dma.
+    // block_samples is how often the DSP raises its interrupt, counting both
+    // channels' samples. We don't use it, but it still has to be acknowledged.
+    fn begin_transfer(&self, buf_phys_addr: u32, buf_bytes: u32, block_samples: u16) {
+        self.dma.begin_auto_init_playback(buf_phys_addr, buf_bytes);
+
+        self.write_dsp(Self::OUTPUT_16_AUTO_INIT);
+        self.write_dsp(Self::FORMAT_SIGNED_STEREO);
+        let count = block_samples - 1;
+        self.write_dsp((count & 0xFF) as u8);
+        self.write_dsp((count >> 8) as u8);
+    }
+
+    // Since the IRQ is masked, we check the mixer to see if the DSP is
+    // waiting on us. In auto-init mode it keeps playing regardless,
+    // but the interrupt line stays raised until we read the ack port.
+    fn acknowledge_irq(&self) {
+        if self.read_mixer(Self::MIXER_IRQ_STATUS) & (1 << 1) != 0 {
+            let mut ack = Port::<u8>::new(self.base_port + Self::DSP_ACK_16);
+            unsafe { ack.read() };
+        }
+    }
+
+    // Byte offset of the sample the card is reading from the DMA buffer
+    fn playback_position(&self, buf_bytes: u32) -> u32 {
+        let remaining_bytes = self.dma.remaining_words() as u32 * 2;
+        (buf_bytes - remaining_bytes.min(buf_bytes)) % buf_bytes
+    }
+}
+
+// Reads the POST diagnostic port, which does nothing but takes about a microsecond
+fn io_delay(micros: usize) {
+    let mut post = Port::<u8>::new(0x80);
+    for _ in 0..micros {
+        unsafe { post.read() };
+    }
+}
@@ -0,0 +1,91 @@
+use volatile::Volatile;
+
+use crate::{
+    audio::FrameSource,
+    phys_alloc::{DualPtr32, PhysAllocator},
+};
+
+use super::Sb16;
+
+// The whole DMA buffer has to fit in one 64KiB page, so we take all of it.
+// That's ~340ms of stereo audio at 48kHz.
+const SAMPLES_IN_BLOB: usize = 0x8000;
+const BYTES_IN_BLOB: u32 = (SAMPLES_IN_BLOB * size_of::<i16>()) as u32;
+// The DSP sees one big ring, but we refill it in blocks the same way
+// the AC97 MusicLoop refills its buffers.
+const NUM_BLOCKS: usize = 16;
+const SAMPLES_PER_BLOCK: usize = SAMPLES_IN_BLOB / NUM_BLOCKS;
+type SamplesBlob = [Volatile<i16>; SAMPLES_IN_BLOB];
+
+pub struct MusicLoop<'a> {
+    sb16: Sb16,
+    samples_blob: DualPtr32<'a, SamplesBlob>,
+    last_block_filled: usize,
+}
+
+impl<'a> MusicLoop<'a> {
+    // Fails if we can't get a buffer the DMA controller can reach.
+    pub fn new(phys_alloc: &mut PhysAllocator, sb16: Sb16) -> Option<Self> {
+        let samples_blob = phys_alloc.alloc_isa_dma::<SamplesBlob>()?;
+
+        Some(Self {
+            sb16,
+            samples_blob,
+            last_block_filled: 0,
+        })
+    }
+
+    fn fill_block(&mut self, block: usize, source: &mut dyn FrameSource) {
+        let start = block * SAMPLES_PER_BLOCK;
+        for i in (start..start + SAMPLES_PER_BLOCK).step_by(2) {
+            let [left, right] = source.next_frame();
+            self.samples_blob.rw_virt[i] = Volatile::new(left);
+            self.samples_blob.rw_virt[i + 1] = Volatile::new(right);
+        }
+    }
+
+    // starts the loop
+    pub fn play(&mut self, source: &mut dyn FrameSource) {
+        for block in 0..NUM_BLOCKS {
+            self.fill_block(block, source);
+        }
+        self.last_block_filled = NUM_BLOCKS - 1;
+
+        self.sb16.init();
+        // one interrupt per block, we still have to acknowledge them
+        self.sb16.begin_transfer(
+            self.samples_blob.r_phys,
+            BYTES_IN_BLOB,
+            SAMPLES_PER_BLOCK as u16,
+        );
+    }
+
+    // must be called repeatedly after the transfer is started
+    // to continue to supply audio frames
+    pub fn wind(&mut self, source: &mut dyn FrameSource) {
+        self.sb16.acknowledge_irq();
+
+        let position = self.sb16.playback_position(BYTES_IN_BLOB) as usize;
+        let current_block = position / size_of::<i16>() / SAMPLES_PER_BLOCK;
+
+        // Unlike the AC97 there is no last valid entry register,
+        // the DSP just keeps going around the ring. So everything between
+        // the last block we filled and the one playing is fair game.
+        let mut i = (self.last_block_filled + 1) % NUM_BLOCKS;
+        while i != current_block {
+            self.fill_block(i, source);
+            i = (i + 1) % NUM_BLOCKS;
+        }
+
+        self.last_block_filled = (current_block + NUM_BLOCKS - 1) % NUM_BLOCKS;
+    }
+
+    // How many frames we've filled that the card has yet to play,
+    // from where the DSP is reading up to the end of the last block we filled
+    pub fn frames_queued(&self) -> u32 {
+        let position = self.sb16.playback_position(BYTES_IN_BLOB) as usize / size_of::<i16>();
+        let filled_to = (self.last_block_filled + 1) * SAMPLES_PER_BLOCK;
+        let samples = (filled_to + SAMPLES_IN_BLOB - position) % SAMPLES_IN_BLOB;
+        (samples / 2) as u32
+    }
+}
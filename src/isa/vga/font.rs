@@ -0,0 +1,105 @@
+use super::{
+    read_indexed, write_indexed, Mode, Vga, GLYPH_HEIGHT, GLYPH_STRIDE, GRAPHICS_DATA,
+    GRAPHICS_INDEX, SEQUENCER_DATA, SEQUENCER_INDEX,
+};
+
+// Text mode draws each character from a bitmap in plane 2 of VGA memory,
+// which we can rewrite with our own, see https://wiki.osdev.org/VGA_Fonts
+// The same glyphs are used by the graphics modes' text, through Vga::font.
+
+// One row per byte, top to bottom, with the leftmost pixel in bit 7
+pub type Glyph = [u8; GLYPH_HEIGHT];
+
+// Sequencer and graphics controller registers for getting at font memory
+const SEQUENCER_CLOCKING_MODE: u8 = 0x1;
+const SEQUENCER_MAP_MASK: u8 = 0x2;
+const SEQUENCER_MEMORY_MODE: u8 = 0x4;
+const GRAPHICS_READ_MAP: u8 = 0x4;
+const GRAPHICS_MODE: u8 = 0x5;
+const GRAPHICS_MISC: u8 = 0x6;
+// and for how wide characters are
+const MISC_CLOCK_SELECT: u8 = 0x0C;
+const CLOCKING_MODE_8_DOTS: u8 = 0x01;
+const ATTRIBUTE_PANNING: usize = 0x13;
+
+impl Vga<'_> {
+    // Replaces glyphs first, first + 1, and so on. They're kept through
+    // mode changes, and only reach the screen once we're back in text mode.
+    pub fn set_glyphs(&mut self, first: u8, glyphs: &[Glyph]) {
+        for (i, glyph) in glyphs.iter().enumerate() {
+            let start = (first as usize + i) * GLYPH_STRIDE;
+            self.font[start..start + GLYPH_HEIGHT].copy_from_slice(glyph);
+        }
+        if self.mode == Mode::Text {
+            self.write_font();
+        }
+    }
+
+    // Text mode starts out with characters 9 pixels wide, where only the box drawing
+    // ones (0xC0 to 0xDF) repeat their last column into the 9th, and everything
+    // else gets a gap. At 8 pixels wide any glyph can join up with the next one.
+    pub fn set_8_dot_characters(&mut self) {
+        let registers = &mut self.text_registers;
+        // The 25MHz clock, for 640 pixels across instead of 720
+        registers.misc &= !MISC_CLOCK_SELECT;
+        registers.sequencer[SEQUENCER_CLOCKING_MODE as usize] |= CLOCKING_MODE_8_DOTS;
+        // 9 pixel characters are shifted by one
+        registers.attribute[ATTRIBUTE_PANNING] = 0;
+        if self.mode == Mode::Text {
+            self.text_registers.write();
+        }
+    }
+
+    pub(super) fn read_font(&mut self) {
+        with_font_memory(|| {
+            for (byte, memory) in self.font.iter_mut().zip(self.window.iter()) {
+                *byte = memory.read();
+            }
+        });
+    }
+
+    pub(super) fn write_font(&mut self) {
+        with_font_memory(|| {
+            for (memory, byte) in self.window.iter_mut().zip(self.font.iter()) {
+                memory.write(*byte);
+            }
+        });
+    }
+}
+
+// Runs f with plane 2 (the font) showing through the window at 0xA0000,
+// byte for byte, then puts everything back how it was
+fn with_font_memory(f: impl FnOnce()) {
+    let map_mask = read_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, SEQUENCER_MAP_MASK);
+    let memory_mode = read_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, SEQUENCER_MEMORY_MODE);
+    let read_map = read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_READ_MAP);
+    let graphics_mode = read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_MODE);
+    let graphics_misc = read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_MISC);
+
+    // Only write to plane 2, without odd/even or chain 4 addressing
+    write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, SEQUENCER_MAP_MASK, 1 << 2);
+    write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, SEQUENCER_MEMORY_MODE, 0x06);
+    // Read from plane 2, in read mode 0 without odd/even
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_READ_MAP, 2);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_MODE, 0x00);
+    // Map VGA memory at 0xA0000 for 64KiB, still in text mode
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_MISC, 0x04);
+
+    f();
+
+    write_indexed(
+        SEQUENCER_INDEX,
+        SEQUENCER_DATA,
+        SEQUENCER_MAP_MASK,
+        map_mask,
+    );
+    write_indexed(
+        SEQUENCER_INDEX,
+        SEQUENCER_DATA,
+        SEQUENCER_MEMORY_MODE,
+        memory_mode,
+    );
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_READ_MAP, read_map);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_MODE, graphics_mode);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, GRAPHICS_MISC, graphics_misc);
+}
@@ -0,0 +1,267 @@
+use volatile::Volatile;
+use x86_64::instructions::port::Port;
+
+use crate::phys_alloc::PhysAllocator;
+
+mod font;
+
+pub use font::Glyph;
+
+// The VGA itself, programmed through its registers rather than the BIOS,
+// which we can't call from long mode anyway.
+// I followed https://wiki.osdev.org/VGA_Hardware, and the mode 13h register
+// values are from Chris Giese's modes.c, which is linked from there.
+// QEMU's -vga std (its default) does all of this.
+
+// The 64KiB window of VGA memory at 0xA0000
+const WINDOW_PHYS_ADDR: u64 = 0xA0000;
+const WINDOW_BYTES: usize = 0x10000;
+
+pub const GRAPHICS_WIDTH: usize = 320;
+pub const GRAPHICS_HEIGHT: usize = 200;
+pub const PIXELS: usize = GRAPHICS_WIDTH * GRAPHICS_HEIGHT;
+
+// Text mode keeps its font in plane 2, with room for 32 rows per character,
+// though the standard font only uses the first 16
+pub const GLYPHS: usize = 256;
+pub const GLYPH_STRIDE: usize = 32;
+pub const GLYPH_HEIGHT: usize = 16;
+pub const FONT_BYTES: usize = GLYPHS * GLYPH_STRIDE;
+
+// Ports
+const ATTRIBUTE_INDEX: u16 = 0x3C0;
+const ATTRIBUTE_READ: u16 = 0x3C1;
+const MISC_WRITE: u16 = 0x3C2;
+const SEQUENCER_INDEX: u16 = 0x3C4;
+const SEQUENCER_DATA: u16 = 0x3C5;
+const DAC_READ_INDEX: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+const MISC_READ: u16 = 0x3CC;
+const GRAPHICS_INDEX: u16 = 0x3CE;
+const GRAPHICS_DATA: u16 = 0x3CF;
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+// Reading this resets the attribute controller to expect an index next
+const INPUT_STATUS: u16 = 0x3DA;
+
+// Attribute controller index bit 5, which lets it show the palette again
+const PALETTE_ADDRESS_SOURCE: u8 = 0x20;
+
+const SEQUENCER_RESET: u8 = 0x0;
+
+const SEQUENCER_REGISTERS: usize = 5;
+const CRTC_REGISTERS: usize = 25;
+const GRAPHICS_REGISTERS: usize = 9;
+const ATTRIBUTE_REGISTERS: usize = 21;
+
+// Everything that picks a video mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Registers {
+    misc: u8,
+    sequencer: [u8; SEQUENCER_REGISTERS],
+    crtc: [u8; CRTC_REGISTERS],
+    graphics: [u8; GRAPHICS_REGISTERS],
+    attribute: [u8; ATTRIBUTE_REGISTERS],
+}
+
+// 320x200 with one byte per pixel, each byte picking one of the 256 palette colours
+const MODE_13H: Registers = Registers {
+    misc: 0x63,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x0E],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F, 0x41, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Text,
+    Graphics320x200,
+    // Something else has the screen, like the Bochs display adapter,
+    // which has to let go of it before setting any other mode
+    Elsewhere,
+}
+
+// Red, green and blue, from 0 to 63
+pub type PaletteColor = [u8; 3];
+
+pub struct Vga<'a> {
+    window: &'a mut [Volatile<u8>; WINDOW_BYTES],
+    mode: Mode,
+    // How text mode was set up when we got here, so we can put it all back.
+    // Mode 13h writes all over font memory, and we change the palette.
+    text_registers: Registers,
+    text_palette: [PaletteColor; 256],
+    font: [u8; FONT_BYTES],
+}
+
+impl<'a> Vga<'a> {
+    // Has to be called while we're still in the text mode the bootloader left us in
+    pub fn new(phys_alloc: &PhysAllocator) -> Self {
+        let mut vga = Self {
+            window: phys_alloc.device_memory(WINDOW_PHYS_ADDR),
+            mode: Mode::Text,
+            text_registers: Registers::read(),
+            text_palette: [[0; 3]; 256],
+            font: [0; FONT_BYTES],
+        };
+        vga.read_palette();
+        vga.read_font();
+        vga
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // Leaves whatever was on screen behind, so redraw everything afterwards
+    pub fn set_mode(&mut self, mode: Mode) {
+        if mode == self.mode {
+            return;
+        }
+        match mode {
+            Mode::Graphics320x200 => MODE_13H.write(),
+            Mode::Elsewhere => {}
+            Mode::Text => {
+                self.text_registers.write();
+                self.write_font();
+                let palette = self.text_palette;
+                self.set_palette(0, &palette);
+            }
+        }
+        self.mode = mode;
+    }
+
+    // Sets colours first, first + 1, and so on
+    pub fn set_palette(&mut self, first: u8, colors: &[PaletteColor]) {
+        unsafe {
+            Port::<u8>::new(DAC_WRITE_INDEX).write(first);
+            let mut data = Port::<u8>::new(DAC_DATA);
+            for color in colors {
+                for channel in color {
+                    data.write(*channel);
+                }
+            }
+        }
+    }
+
+    // One byte per pixel, from the top left across, but only in Graphics320x200
+    pub fn pixels(&mut self) -> &mut [Volatile<u8>] {
+        debug_assert_eq!(self.mode, Mode::Graphics320x200);
+        &mut self.window[..PIXELS]
+    }
+
+    // Row r of glyph c is byte c * GLYPH_STRIDE + r, with the leftmost pixel in bit 7
+    pub fn font(&self) -> &[u8] {
+        &self.font
+    }
+
+    fn read_palette(&mut self) {
+        unsafe {
+            Port::<u8>::new(DAC_READ_INDEX).write(0);
+            let mut data = Port::<u8>::new(DAC_DATA);
+            for color in self.text_palette.iter_mut() {
+                for channel in color.iter_mut() {
+                    *channel = data.read();
+                }
+            }
+        }
+    }
+}
+
+impl Registers {
+    fn read() -> Self {
+        let mut registers = Self {
+            misc: unsafe { Port::<u8>::new(MISC_READ).read() },
+            sequencer: [0; SEQUENCER_REGISTERS],
+            crtc: [0; CRTC_REGISTERS],
+            graphics: [0; GRAPHICS_REGISTERS],
+            attribute: [0; ATTRIBUTE_REGISTERS],
+        };
+        for (i, value) in registers.sequencer.iter_mut().enumerate() {
+            *value = read_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, i as u8);
+        }
+        for (i, value) in registers.crtc.iter_mut().enumerate() {
+            *value = read_indexed(CRTC_INDEX, CRTC_DATA, i as u8);
+        }
+        for (i, value) in registers.graphics.iter_mut().enumerate() {
+            *value = read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, i as u8);
+        }
+        unsafe {
+            for (i, value) in registers.attribute.iter_mut().enumerate() {
+                Port::<u8>::new(INPUT_STATUS).read();
+                Port::<u8>::new(ATTRIBUTE_INDEX).write(i as u8);
+                *value = Port::<u8>::new(ATTRIBUTE_READ).read();
+            }
+            // Reading the attribute registers blanks the screen until we say otherwise
+            Port::<u8>::new(INPUT_STATUS).read();
+            Port::<u8>::new(ATTRIBUTE_INDEX).write(PALETTE_ADDRESS_SOURCE);
+        }
+        registers
+    }
+
+    fn write(&self) {
+        // Hold the sequencer in reset while the clock might change
+        write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, SEQUENCER_RESET, 0x01);
+        unsafe {
+            Port::<u8>::new(MISC_WRITE).write(self.misc);
+        }
+        for (i, value) in self.sequencer.iter().enumerate().skip(1) {
+            write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, i as u8, *value);
+        }
+        write_indexed(
+            SEQUENCER_INDEX,
+            SEQUENCER_DATA,
+            SEQUENCER_RESET,
+            self.sequencer[0],
+        );
+
+        // The first 8 CRTC registers are write protected by bit 7 of register 0x11,
+        // and bit 7 of register 3 has to be set for them to work
+        let mut crtc = self.crtc;
+        crtc[0x03] |= 0x80;
+        crtc[0x11] &= !0x80;
+        write_indexed(CRTC_INDEX, CRTC_DATA, 0x03, crtc[0x03]);
+        write_indexed(CRTC_INDEX, CRTC_DATA, 0x11, crtc[0x11]);
+        for (i, value) in crtc.iter().enumerate() {
+            write_indexed(CRTC_INDEX, CRTC_DATA, i as u8, *value);
+        }
+
+        for (i, value) in self.graphics.iter().enumerate() {
+            write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, i as u8, *value);
+        }
+
+        // The attribute controller has one port for both index and data,
+        // and flips between them on every write
+        unsafe {
+            for (i, value) in self.attribute.iter().enumerate() {
+                Port::<u8>::new(INPUT_STATUS).read();
+                Port::<u8>::new(ATTRIBUTE_INDEX).write(i as u8);
+                Port::<u8>::new(ATTRIBUTE_INDEX).write(*value);
+            }
+            Port::<u8>::new(INPUT_STATUS).read();
+            Port::<u8>::new(ATTRIBUTE_INDEX).write(PALETTE_ADDRESS_SOURCE);
+        }
+    }
+}
+
+fn read_indexed(index_port: u16, data_port: u16, index: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(index_port).write(index);
+        Port::<u8>::new(data_port).read()
+    }
+}
+
+fn write_indexed(index_port: u16, data_port: u16, index: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(index_port).write(index);
+        Port::<u8>::new(data_port).write(value);
+    }
+}
@@ -1,9 +1,16 @@
 #![no_std]
 #![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
+mod audio;
+mod isa;
+mod pc_speaker;
 mod pci;
 mod phys_alloc;
 mod spacefox;
+mod trig;
 
 use bootloader::BootInfo;
 use crossbeam::atomic::AtomicCell;
@@ -15,6 +22,9 @@ use spacefox::Game;
 
 #[no_mangle]
 pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    #[cfg(test)]
+    test_main();
+
     BOOT_INFO.store(Some(boot_info));
     HandlerTable::new()
         .keyboard(key)
@@ -33,11 +43,12 @@ fn cpu_loop() -> ! {
     let mut phys_alloc = PhysAllocator::new(info).unwrap();
 
     let devs = scan_pci_devices();
-    let ac97 = devs.ac97.unwrap();
 
-    let mut game = Game::new(&mut phys_alloc, ac97);
+    let mut game = Game::new(&mut phys_alloc, devs);
 
     loop {
+        game.poll();
+
         if let Ok(_) = TICKED.compare_exchange(true, false) {
             game.tick()
         }
@@ -61,3 +72,24 @@ fn tick() {
 fn startup() {
     clear_screen();
 }
+
+// `cargo test` boots the kernel in QEMU (see test-args in Cargo.toml), runs every
+// #[test_case] and exits QEMU through its isa-debug-exit device, like
+// https://os.phil-opp.com/testing/ does. Pluggable Interrupt OS owns the panic
+// handler, which halts, so a failing test shows up as bootimage's test-timeout.
+#[cfg(test)]
+fn test_runner(tests: &[&dyn Fn()]) {
+    use pluggable_interrupt_os::serial_println;
+    use x86_64::instructions::port::Port;
+
+    const ISA_DEBUG_EXIT_PORT: u16 = 0xF4;
+    // QEMU exits with (code << 1) | 1, which is test-success-exit-code
+    const SUCCESS: u32 = 0x10;
+
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test();
+    }
+    serial_println!("All passed");
+    unsafe { Port::<u32>::new(ISA_DEBUG_EXIT_PORT).write(SUCCESS) };
+}
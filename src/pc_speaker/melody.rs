@@ -0,0 +1,78 @@
+use super::PcSpeaker;
+
+// Equal temperament frequencies (Hz), rounded to the nearest integer.
+// That's well within what the PIT divisor can resolve at these octaves.
+pub const REST: u16 = 0;
+pub const C4: u16 = 262;
+pub const D4: u16 = 294;
+pub const E4: u16 = 330;
+pub const F4: u16 = 349;
+pub const G4: u16 = 392;
+pub const A4: u16 = 440;
+pub const B4: u16 = 494;
+pub const C5: u16 = 523;
+pub const D5: u16 = 587;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pub freq: u16,
+    // how many timer ticks the note lasts
+    pub ticks: u8,
+}
+
+pub const fn n(freq: u16, ticks: u8) -> Note {
+    Note { freq, ticks }
+}
+
+// Steps through a melody one timer tick at a time.
+// The speaker can only play one square wave at once,
+// so there is no mixing or volume, just the current note.
+pub struct MelodyPlayer<'a> {
+    speaker: PcSpeaker,
+    melody: &'a [Note],
+    next_note: usize,
+    ticks_left: u8,
+    playing: bool,
+}
+
+impl<'a> MelodyPlayer<'a> {
+    pub fn new(speaker: PcSpeaker, melody: &'a [Note]) -> Self {
+        Self {
+            speaker,
+            melody,
+            next_note: 0,
+            ticks_left: 0,
+            playing: false,
+        }
+    }
+
+    // starts the melody over from the first note, looping forever
+    pub fn play(&mut self) {
+        self.next_note = 0;
+        self.ticks_left = 0;
+        self.playing = !self.melody.is_empty();
+        self.wind();
+    }
+
+    // must be called once per timer tick after play()
+    pub fn wind(&mut self) {
+        if !self.playing {
+            return;
+        }
+
+        if self.ticks_left > 0 {
+            self.ticks_left -= 1;
+            return;
+        }
+
+        let note = self.melody[self.next_note];
+        self.speaker.play_tone(note.freq as u32);
+        // this tick counts as the first one of the note
+        self.ticks_left = note.ticks.saturating_sub(1);
+
+        self.next_note += 1;
+        if self.next_note >= self.melody.len() {
+            self.next_note = 0;
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use x86_64::instructions::port::Port;
+
+pub mod melody;
+pub mod pwm;
+
+// Everything here comes from https://wiki.osdev.org/PC_Speaker
+// and https://wiki.osdev.org/Programmable_Interval_Timer
+// Channel 0 of the PIT belongs to pluggable_interrupt_os for the timer
+// interrupt, so we only ever touch channel 2, which is wired to the speaker.
+
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+// Also known as "keyboard controller port B"
+const SPEAKER_CONTROL: u16 = 0x61;
+
+// The PIT input clock, which is the same on every PC
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+// Bits of SPEAKER_CONTROL:
+// Bit 0=Gate input of PIT channel 2 (the counter only runs while this is set)
+// Bit 1=Connect PIT channel 2 output to the speaker
+// Bit 5=Current level of PIT channel 2 output (read only)
+const GATE_ENABLE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+// PIT command byte: channel in bits 6-7, access mode in bits 4-5,
+// operating mode in bits 1-3, BCD in bit 0 (we always leave it binary)
+const SELECT_CHANNEL_2: u8 = 0b10 << 6;
+const ACCESS_LOHI: u8 = 0b11 << 4;
+const MODE_INTERRUPT_ON_TERMINAL_COUNT: u8 = 0b000 << 1;
+const MODE_SQUARE_WAVE: u8 = 0b011 << 1;
+
+// Every PC has one of these, so unlike the PCI devices
+// there is nothing to detect.
+#[derive(Debug)]
+pub struct PcSpeaker {
+    _private: (),
+}
+
+impl PcSpeaker {
+    pub fn new() -> Self {
+        let mut me = Self { _private: () };
+        me.stop();
+        me
+    }
+
+    // Plays a square wave until stop() or another play_tone().
+    // A frequency of 0 is treated as a rest.
+    pub fn play_tone(&mut self, freq: u32) {
+        if freq == 0 {
+            self.stop();
+            return;
+        }
+
+        // The reload value is 16 bits, so anything under ~19Hz can't be played
+        let divisor = (PIT_FREQUENCY / freq).clamp(1, u16::MAX as u32) as u16;
+
+        self.program_channel_2(MODE_SQUARE_WAVE, divisor);
+        self.modify_control(|x| x | GATE_ENABLE | SPEAKER_ENABLE);
+    }
+
+    pub fn stop(&mut self) {
+        self.modify_control(|x| x & !(GATE_ENABLE | SPEAKER_ENABLE));
+    }
+
+    // For PWM, channel 2 is used as a one shot timer:
+    // the output drops as soon as the count is written
+    // and rises again once it reaches zero.
+    fn begin_one_shots(&mut self) {
+        self.program_channel_2(MODE_INTERRUPT_ON_TERMINAL_COUNT, 1);
+        self.modify_control(|x| x | GATE_ENABLE | SPEAKER_ENABLE);
+    }
+
+    // begin_one_shots() must be called first!
+    // In mode 0 writing a new count restarts the counter,
+    // so we don't need to resend the command byte.
+    fn one_shot(&mut self, count: u16) {
+        let mut channel_2 = Port::<u8>::new(PIT_CHANNEL_2);
+        unsafe {
+            channel_2.write((count & 0xFF) as u8);
+            channel_2.write((count >> 8) as u8);
+        }
+    }
+
+    // Runs channel 2 for 'count' PIT cycles with the speaker disconnected
+    // and busy waits until it finishes, which makes it a clock we can
+    // measure other things against.
+    fn wait_pit_cycles(&mut self, count: u16) {
+        self.modify_control(|x| (x & !SPEAKER_ENABLE) | GATE_ENABLE);
+        self.program_channel_2(MODE_INTERRUPT_ON_TERMINAL_COUNT, count);
+
+        let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+        while unsafe { control.read() } & CHANNEL_2_OUTPUT == 0 {}
+
+        self.stop();
+    }
+
+    fn program_channel_2(&mut self, mode: u8, count: u16) {
+        let mut command = Port::<u8>::new(PIT_COMMAND);
+        unsafe { command.write(SELECT_CHANNEL_2 | ACCESS_LOHI | mode) };
+        self.one_shot(count);
+    }
+
+    fn modify_control(&mut self, f: impl Fn(u8) -> u8) {
+        let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+        unsafe {
+            let tmp = control.read();
+            control.write(f(tmp));
+        }
+    }
+}
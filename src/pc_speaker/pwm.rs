@@ -0,0 +1,84 @@
+use core::arch::x86_64::_rdtsc;
+
+use crate::audio::{FrameSource, SAMPLE_RATE};
+
+use super::{PcSpeaker, PIT_FREQUENCY};
+
+// Plays 16 bit PCM through the speaker by pulse width modulation:
+// every output sample we restart a PIT one shot whose length is
+// proportional to the amplitude, so the speaker cone spends that fraction
+// of the sample period pulled in. It is crude, but recognizable.
+// See https://wiki.osdev.org/PC_Speaker#Playing_sound_samples
+
+// Every third frame, i.e. 16kHz.
+// Any faster and the one shots get too short to have much resolution.
+const DECIMATION: usize = 3;
+const OUTPUT_RATE: u64 = SAMPLE_RATE as u64 / DECIMATION as u64;
+const COUNTS_PER_SAMPLE: u32 = PIT_FREQUENCY / OUTPUT_RATE as u32;
+
+// After a stall we skip at most this many samples' worth of the music (~1ms)
+// to stay in step, and drop the rest of the gap rather than mixing audio
+// nobody will hear, which would only make the next stall longer
+const MAX_CATCH_UP_SAMPLES: u64 = 16;
+
+// ~10ms worth of PIT cycles, used to measure the TSC
+const CALIBRATION_COUNTS: u16 = (PIT_FREQUENCY / 100) as u16;
+
+pub struct PwmLoop {
+    speaker: PcSpeaker,
+    tsc_per_sample: u64,
+    next_sample_tsc: u64,
+}
+
+impl PwmLoop {
+    pub fn new(mut speaker: PcSpeaker) -> Self {
+        // We don't get an interrupt for every sample, so we have to
+        // poll the time stamp counter instead, which means we need to know
+        // how fast it runs relative to something we trust.
+        let start = unsafe { _rdtsc() };
+        speaker.wait_pit_cycles(CALIBRATION_COUNTS);
+        let end = unsafe { _rdtsc() };
+
+        let tsc_per_second = (end - start) * PIT_FREQUENCY as u64 / CALIBRATION_COUNTS as u64;
+
+        Self {
+            speaker,
+            tsc_per_sample: (tsc_per_second / OUTPUT_RATE).max(1),
+            next_sample_tsc: 0,
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.speaker.begin_one_shots();
+        self.next_sample_tsc = unsafe { _rdtsc() };
+    }
+
+    // must be called as often as possible after play(),
+    // anything slower than OUTPUT_RATE will be heard as dropouts
+    pub fn wind(&mut self, source: &mut dyn FrameSource) {
+        let now = unsafe { _rdtsc() };
+        if now < self.next_sample_tsc {
+            return;
+        }
+
+        // If the game kept us waiting, skip ahead instead of playing
+        // the song in slow motion, but only a little, see MAX_CATCH_UP_SAMPLES
+        let samples_due = (now - self.next_sample_tsc) / self.tsc_per_sample + 1;
+        self.next_sample_tsc += samples_due * self.tsc_per_sample;
+
+        let skipped = (samples_due - 1).min(MAX_CATCH_UP_SAMPLES);
+        for _ in 0..skipped as usize * DECIMATION {
+            source.next_frame();
+        }
+
+        let [left, right] = source.next_frame();
+        let mono = (left as i32 + right as i32) / 2;
+        for _ in 1..DECIMATION {
+            source.next_frame();
+        }
+
+        // map [-32768, 32767] onto [1, COUNTS_PER_SAMPLE]
+        let count = 1 + (mono + 32768) as u32 * (COUNTS_PER_SAMPLE - 1) / 65536;
+        self.speaker.one_shot(count as u16);
+    }
+}
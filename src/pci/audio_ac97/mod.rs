@@ -17,11 +17,10 @@ const MAX_SAMPLES_PER_BUF: u16 = 0xFFFE;
 // Defaults that we won't change:
 const SAMPLE_SIZE: usize = size_of::<i16>();
 const NUM_CHANNELS: usize = 2;
+pub const SAMPLE_RATE: usize = 48_000;
 // Good to know:
-const SAMPLES_PER_BUF: u16 = MAX_SAMPLES_PER_BUF;
-const BYTES_PER_BUF: u32 = SAMPLES_PER_BUF as u32 * SAMPLE_SIZE as u32;
-// const SAMPLES_PER_FRAME: usize = NUM_CHANNELS;
-// const FRAMES_PER_BUF: usize = SAMPLES_PER_BUF as usize / SAMPLES_PER_FRAME;
+const SAMPLES_PER_FRAME: usize = NUM_CHANNELS;
+// How many buffers (and so how much memory) we queue is up to music_loop::RingConfig
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 #[repr(packed)]
@@ -57,6 +56,25 @@ impl AudioAc97 {
     const LAST_VALID_ENTRY_OFFSET: u16 = 0x05;
     const CURRENT_PROCESSED_ENTRY_OFFSET: u16 = 0x04;
     const TRANSFER_CONTROL_OFFSET: u16 = 0x0B;
+    const STATUS_OFFSET: u16 = 0x06;
+    const POSITION_IN_BUFFER_OFFSET: u16 = 0x08;
+
+    // From https://wiki.osdev.org/AC97#Native%20Audio%20Bus%20Master%20registers
+    // Bits of the PCM OUT status register:
+    // Bit 0=DMA controller halted (only after Last Valid Buffer or a stop)
+    // Bit 1=Current entry is the last valid entry
+    // Bit 2=Last valid buffer was finished (write 1 to clear)
+    // Bit 3=Buffer with interrupt bit was finished (write 1 to clear)
+    // Bit 4=FIFO error (write 1 to clear)
+    const STATUS_DMA_HALTED: u16 = 1 << 0;
+    const STATUS_AT_LAST_VALID: u16 = 1 << 1;
+    const STATUS_LAST_VALID_DONE: u16 = 1 << 2;
+    const STATUS_BUFFER_DONE: u16 = 1 << 3;
+    const STATUS_FIFO_ERROR: u16 = 1 << 4;
+    const STATUS_WRITE_TO_CLEAR: u16 =
+        Self::STATUS_LAST_VALID_DONE | Self::STATUS_BUFFER_DONE | Self::STATUS_FIFO_ERROR;
+
+    const TRANSFER_SOUND_DATA: u8 = 1 << 0;
 
     pub fn new(bus: u8, slot: u8, header: PciHeaderType0) -> Self {
         Self {
@@ -82,6 +100,34 @@ impl AudioAc97 {
         io_space_bar_write::<u8>(last_valid_entry, buf);
     }
 
+    fn get_status(&self) -> u16 {
+        io_space_bar_read::<u16>(self.buffer_port_base + Self::PCM_OUT + Self::STATUS_OFFSET)
+    }
+
+    // Only the write-to-clear bits in 'bits' are affected
+    fn clear_status(&self, bits: u16) {
+        io_space_bar_write::<u16>(
+            self.buffer_port_base + Self::PCM_OUT + Self::STATUS_OFFSET,
+            bits & Self::STATUS_WRITE_TO_CLEAR,
+        );
+    }
+
+    // How many samples of the current buffer the card has yet to play
+    fn get_samples_left_in_buffer(&self) -> u16 {
+        io_space_bar_read::<u16>(
+            self.buffer_port_base + Self::PCM_OUT + Self::POSITION_IN_BUFFER_OFFSET,
+        )
+    }
+
+    // Once the card halts on the last valid buffer, it needs a new
+    // last valid entry, and (on some cards) the transfer bit set again.
+    // Setting the transfer bit while running is harmless.
+    fn resume_transfer(&self) {
+        let pcm_out_transfer =
+            self.buffer_port_base + Self::PCM_OUT + Self::TRANSFER_CONTROL_OFFSET;
+        io_space_bar_write::<u8>(pcm_out_transfer, Self::TRANSFER_SOUND_DATA);
+    }
+
     fn get_current_buffer(&self) -> u8 {
         let buf = io_space_bar_read::<u8>(
             self.buffer_port_base + Self::PCM_OUT + Self::CURRENT_PROCESSED_ENTRY_OFFSET,
@@ -169,9 +215,8 @@ impl AudioAc97 {
         // If the BDL or the data that any entry in it points to is
         // set up incorrectly, the volume indicator for Qemu should show up,
         // but not show any activity.
-        const TRANSFER_SOUND_DATA: u8 = 1 << 0;
         let pcm_out_transfer =
             self.buffer_port_base + Self::PCM_OUT + Self::TRANSFER_CONTROL_OFFSET;
-        io_space_bar_write::<u8>(pcm_out_transfer, TRANSFER_SOUND_DATA);
+        io_space_bar_write::<u8>(pcm_out_transfer, Self::TRANSFER_SOUND_DATA);
     }
 }
@@ -1,116 +1,257 @@
 use volatile::Volatile;
 
-use crate::phys_alloc::{DualPtr32, PhysAllocator};
+use crate::{
+    audio::{Frame, FrameSource},
+    phys_alloc::{DualPtr32, PhysAllocator},
+};
 
 use super::{
-    AudioAc97, BufferDescriptor, BufferDescriptorList, BYTES_PER_BUF, NUM_BUFFERS, SAMPLES_PER_BUF,
+    AudioAc97, BufferDescriptor, BufferDescriptorList, MAX_SAMPLES_PER_BUF, NUM_BUFFERS,
+    SAMPLES_PER_FRAME, SAMPLE_RATE, SAMPLE_SIZE,
 };
 
-const SAMPLES_IN_BLOB: usize = SAMPLES_PER_BUF as usize * NUM_BUFFERS;
-type SamplesBlob = [Volatile<i16>; SAMPLES_IN_BLOB];
+// The BDL always has NUM_BUFFERS entries, and the card always walks all of them,
+// so latency isn't controlled by how many buffers there are, but by how far
+// ahead of the one playing we are willing to fill them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingConfig {
+    // How many buffers we keep filled past the one that is playing.
+    // At most MAX_QUEUED_BUFFERS, and at least 1.
+    pub queued_buffers: u8,
+    // Must be a whole number of frames, and at most MAX_SAMPLES_PER_BUF
+    pub samples_per_buf: u16,
+}
+
+// If we queued more than half the ring, we couldn't tell the difference
+// between being far ahead of the card and the card having lapped us.
+const MAX_QUEUED_BUFFERS: u8 = NUM_BUFFERS as u8 / 2;
+
+const MOD32_MASK: u8 = NUM_BUFFERS as u8 - 1;
+const _: () = assert!(NUM_BUFFERS == 32); // if this changes, the bit mask won't work
+
+impl RingConfig {
+    // Splits the target latency evenly across queued_buffers buffers.
+    // wind() has to be called more often than one buffer's worth of time,
+    // or the card will run out.
+    pub const fn from_latency_ms(latency_ms: u32, queued_buffers: u8) -> Self {
+        let queued_buffers = if queued_buffers == 0 {
+            1
+        } else if queued_buffers > MAX_QUEUED_BUFFERS {
+            MAX_QUEUED_BUFFERS
+        } else {
+            queued_buffers
+        };
+
+        let frames = latency_ms as usize * SAMPLE_RATE / 1000 / queued_buffers as usize;
+        let mut samples = frames * SAMPLES_PER_FRAME;
+        if samples < SAMPLES_PER_FRAME {
+            samples = SAMPLES_PER_FRAME;
+        }
+        let max = MAX_SAMPLES_PER_BUF as usize / SAMPLES_PER_FRAME * SAMPLES_PER_FRAME;
+        if samples > max {
+            samples = max;
+        }
+
+        Self {
+            queued_buffers,
+            samples_per_buf: samples as u16,
+        }
+    }
+}
+
+// Kept by MusicLoop::wind, so we can tell if the ring is too small
+// or the game is stalling for too long between calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaybackStats {
+    // The card played everything we gave it and halted
+    pub underruns: u32,
+    // The card got to the last buffer we gave it before we refilled,
+    // one step away from an underrun
+    pub last_valid_reached: u32,
+    pub fifo_errors: u32,
+    // Buffers filled since play()
+    pub refills: u32,
+    // The fewest samples the card still had queued when wind() was called
+    pub worst_headroom_samples: u32,
+}
+
+impl PlaybackStats {
+    const fn new() -> Self {
+        Self {
+            underruns: 0,
+            last_valid_reached: 0,
+            fifo_errors: 0,
+            refills: 0,
+            worst_headroom_samples: u32::MAX,
+        }
+    }
+
+    pub fn worst_headroom_ms(&self) -> u32 {
+        if self.worst_headroom_samples == u32::MAX {
+            return 0;
+        }
+        let frames = self.worst_headroom_samples as usize / SAMPLES_PER_FRAME;
+        (frames * 1000 / SAMPLE_RATE) as u32
+    }
+}
 
 pub struct MusicLoop<'a> {
     ac97: AudioAc97,
-    music_data: &'a [i16],
-    music_data_read_head: usize,
-    samples_blob: DualPtr32<'a, SamplesBlob>,
+    config: RingConfig,
+    stats: PlaybackStats,
+    samples_blob: DualPtr32<'a, [Volatile<i16>]>,
     buffer_descriptor_list: DualPtr32<'a, BufferDescriptorList>,
     last_buffer_filled: u8,
 }
 
 impl<'a> MusicLoop<'a> {
-    // Assumes audio is in 16 bit samples
-    pub fn new(phys_alloc: &mut PhysAllocator, music_data: &'a [i16], ac97: AudioAc97) -> Self {
-        let samples_blob = phys_alloc.alloc32::<SamplesBlob>();
+    pub fn new(phys_alloc: &mut PhysAllocator, ac97: AudioAc97, config: RingConfig) -> Self {
+        debug_assert!(config.queued_buffers >= 1 && config.queued_buffers <= MAX_QUEUED_BUFFERS);
+        debug_assert!((config.samples_per_buf as usize).is_multiple_of(SAMPLES_PER_FRAME));
+        debug_assert!(config.samples_per_buf <= MAX_SAMPLES_PER_BUF);
+
+        let samples_per_buf = config.samples_per_buf as usize;
+        let bytes_per_buf = (samples_per_buf * SAMPLE_SIZE) as u32;
+
+        let samples_blob = phys_alloc.alloc32_slice::<Volatile<i16>>(samples_per_buf * NUM_BUFFERS);
         let buffer_descriptor_list = phys_alloc.alloc32::<BufferDescriptorList>();
 
         for i in 0..NUM_BUFFERS {
             buffer_descriptor_list.rw_virt[i] = Volatile::new(BufferDescriptor {
-                physical_addr: samples_blob.r_phys + BYTES_PER_BUF * i as u32,
-                num_samples: SAMPLES_PER_BUF as u16,
+                physical_addr: samples_blob.r_phys + bytes_per_buf * i as u32,
+                num_samples: config.samples_per_buf,
                 control: 0, // no interrupt, no stopping
             })
         }
 
-        let mut me = Self {
+        Self {
             ac97,
-            music_data,
-            music_data_read_head: 0,
+            config,
+            stats: PlaybackStats::new(),
             samples_blob,
             buffer_descriptor_list,
             last_buffer_filled: 0,
-        };
-
-        me.fill_sound_blob();
-
-        me
+        }
     }
 
-    // this is called in new, when any MusicLoop is created
-    // because we have to ensure this happens before play
-    fn fill_sound_blob(&mut self) {
-        for i in 0..self.samples_blob.rw_virt.len() {
-            self.samples_blob.rw_virt[i] =
-                Volatile::new(self.music_data[i % self.music_data.len()]);
+    // this is called in play, because we have to have
+    // something in the buffers before the card starts
+    fn fill_sound_blob(&mut self, source: &mut dyn FrameSource) {
+        // The card starts on buffer 0, so it gets filled too
+        for i in 0..=self.config.queued_buffers {
+            self.fill_buffer(i, source);
+        }
+        self.last_buffer_filled = self.config.queued_buffers;
+    }
 
-            self.music_data_read_head += 1;
-            if self.music_data_read_head >= self.music_data.len() {
-                self.music_data_read_head = 0;
-            }
+    fn fill_buffer(&mut self, buf: u8, source: &mut dyn FrameSource) {
+        let samples_per_buf = self.config.samples_per_buf as usize;
+        let start = buf as usize * samples_per_buf;
+        for write_pos in (start..start + samples_per_buf).step_by(SAMPLES_PER_FRAME) {
+            let [left, right] = source.next_frame();
+            self.samples_blob.rw_virt[write_pos] = Volatile::new(left);
+            self.samples_blob.rw_virt[write_pos + 1] = Volatile::new(right);
         }
-        // samples_blob strecthes accross all buffers, so after the
-        // for loop all buffers are valid
-        self.last_buffer_filled = NUM_BUFFERS as u8 - 1;
     }
 
     // starts the loop
-    pub fn play(&mut self) {
+    pub fn play(&mut self, source: &mut dyn FrameSource) {
+        self.fill_sound_blob(source);
         self.ac97.init();
         self.ac97
-            .begin_transfer(self.buffer_descriptor_list.r_phys, NUM_BUFFERS as u8 - 1);
+            .begin_transfer(self.buffer_descriptor_list.r_phys, self.last_buffer_filled);
+    }
+
+    pub fn stats(&self) -> PlaybackStats {
+        self.stats
+    }
+
+    // How many frames we've filled that the card has yet to play,
+    // so how far the speakers are behind whatever we're about to mix
+    pub fn frames_queued(&self) -> u32 {
+        let current_buf = self.ac97.get_current_buffer();
+        let ahead = self.last_buffer_filled.wrapping_sub(current_buf) & MOD32_MASK;
+        if ahead > self.config.queued_buffers {
+            // The card lapped us, and the next wind() starts over from it
+            return 0;
+        }
+        let samples = self.ac97.get_samples_left_in_buffer() as u32
+            + ahead as u32 * self.config.samples_per_buf as u32;
+        samples / SAMPLES_PER_FRAME as u32
+    }
+
+    // Reads back the last out.len() frames the card played, oldest first,
+    // ending right where it is now. Buffers behind the card keep what they
+    // last played until we fill them again, which is never less than
+    // (NUM_BUFFERS - MAX_QUEUED_BUFFERS) buffers' worth of frames back.
+    pub fn recent_frames(&self, out: &mut [Frame]) {
+        let samples_per_buf = self.config.samples_per_buf as usize;
+        let ring_samples = samples_per_buf * NUM_BUFFERS;
+        debug_assert!(out.len() * SAMPLES_PER_FRAME <= ring_samples);
+        let current_buf = self.ac97.get_current_buffer() as usize;
+        let played = samples_per_buf - self.ac97.get_samples_left_in_buffer() as usize;
+        let play_head = current_buf * samples_per_buf + played;
+
+        let start = play_head + ring_samples - out.len() * SAMPLES_PER_FRAME;
+        for (i, frame) in out.iter_mut().enumerate() {
+            let at = (start + i * SAMPLES_PER_FRAME) % ring_samples;
+            *frame = [
+                self.samples_blob.rw_virt[at].read(),
+                self.samples_blob.rw_virt[at + 1].read(),
+            ];
+        }
     }
 
     // must be called repeatedly after the transfer is started
-    // to continue to supply audio frames
-    pub fn wind(&mut self) {
-        debug_assert!(NUM_BUFFERS == 32); // if this changes, the bit mask won't work;
-        const MOD32_MASK: u8 = 0b11111;
+    // to continue to supply audio frames, at least once every
+    // buffer's worth of time (see RingConfig)
+    pub fn wind(&mut self, source: &mut dyn FrameSource) {
+        let status = self.ac97.get_status();
         let current_buf: u8 = self.ac97.get_current_buffer();
+        let ahead_of_card = |buf: u8| buf.wrapping_sub(current_buf) & MOD32_MASK;
 
-        // if buf != self.last_buffer_filled {
-        //     println!("Bailing {buf}!");
-        //     return;
-        // }
-
-        let fill_to = current_buf.wrapping_sub(1) & MOD32_MASK;
-
-        // println!("Filling {current_buf}, {}", self.last_buffer_filled);
-
-        let mut i = (self.last_buffer_filled + 1) & MOD32_MASK;
-        while i != current_buf {
-            // println!(
-            //     "Filling from [{}..{}), dataon {}",
-            //     (self.last_buffer_filled + 1) & MOD32_MASK,
-            //     current_buf,
-            //     self.music_data_read_head
-            // );
-            let mut buf_write_head = 0;
-            while buf_write_head < SAMPLES_PER_BUF {
-                let write_pos = i as usize * SAMPLES_PER_BUF as usize + buf_write_head as usize;
-                // println!("w {}/{}", write_pos, self.samples_blob.rw_virt.len());
-                self.samples_blob.rw_virt[write_pos] =
-                    Volatile::new(self.music_data[self.music_data_read_head]);
-                buf_write_head += 1;
-                self.music_data_read_head += 1;
-                if self.music_data_read_head >= self.music_data.len() {
-                    self.music_data_read_head = 0;
-                }
+        let halted = status & (AudioAc97::STATUS_DMA_HALTED | AudioAc97::STATUS_LAST_VALID_DONE);
+        if halted != 0 {
+            // The card ran out and is sitting on a buffer it already played.
+            // Everything we filled is gone, so start again right after it.
+            self.stats.underruns += 1;
+            self.last_buffer_filled = current_buf;
+        } else {
+            if status & AudioAc97::STATUS_AT_LAST_VALID != 0 {
+                self.stats.last_valid_reached += 1;
             }
 
-            i = (i + 1) & MOD32_MASK;
+            let headroom = self.ac97.get_samples_left_in_buffer() as u32
+                + ahead_of_card(self.last_buffer_filled) as u32
+                    * self.config.samples_per_buf as u32;
+            self.stats.worst_headroom_samples = self.stats.worst_headroom_samples.min(headroom);
+        }
+
+        if status & AudioAc97::STATUS_FIFO_ERROR != 0 {
+            self.stats.fifo_errors += 1;
+        }
+
+        self.ac97.clear_status(status);
+
+        // If the card has somehow gone past the last buffer we filled
+        // without halting, everything between is stale. There's nothing we can do
+        // about the buffer it is playing right now, so start again right after it.
+        if ahead_of_card(self.last_buffer_filled) > self.config.queued_buffers {
+            self.last_buffer_filled = current_buf;
+        }
+
+        // Only top up to queued_buffers, anything more is just latency
+        while ahead_of_card(self.last_buffer_filled) < self.config.queued_buffers {
+            let next = (self.last_buffer_filled + 1) & MOD32_MASK;
+            self.fill_buffer(next, source);
+            self.last_buffer_filled = next;
+            self.stats.refills += 1;
         }
 
-        self.ac97.set_filled_up_to(fill_to);
-        self.last_buffer_filled = fill_to;
+        self.ac97.set_filled_up_to(self.last_buffer_filled);
+
+        if halted != 0 {
+            self.ac97.resume_transfer();
+        }
     }
 }
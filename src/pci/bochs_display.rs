@@ -0,0 +1,127 @@
+use volatile::Volatile;
+
+use crate::{
+    pci::io::{io_space_bar_read, io_space_bar_write, pci_config_modify},
+    phys_alloc::PhysAllocator,
+};
+
+use super::headers::PciHeaderType0;
+
+// The Bochs Graphics Adapter, which is what QEMU's -vga std is as well as a VGA.
+// I followed https://wiki.osdev.org/Bochs_VBE_Extensions
+// Its "DISPI" registers are behind an index and a data port, like the VGA's,
+// and the screen is a plain array of pixels at BAR0, one after another in rows.
+
+pub const VENDOR_ID: u16 = 0x1234;
+pub const DEVICE_ID: u16 = 0x1111;
+
+const DISPI_INDEX: u16 = 0x1CE;
+const DISPI_DATA: u16 = 0x1CF;
+
+// DISPI registers
+const INDEX_ID: u16 = 0x0;
+const INDEX_XRES: u16 = 0x1;
+const INDEX_YRES: u16 = 0x2;
+const INDEX_BPP: u16 = 0x3;
+const INDEX_ENABLE: u16 = 0x4;
+const INDEX_VIRT_WIDTH: u16 = 0x6;
+const INDEX_VIRT_HEIGHT: u16 = 0x7;
+const INDEX_X_OFFSET: u16 = 0x8;
+const INDEX_Y_OFFSET: u16 = 0x9;
+
+// Bits of the enable register
+const ENABLED: u16 = 0x01;
+const LFB_ENABLED: u16 = 0x40;
+
+// Each version adds to the one before, and 0xB0C2 is the first with 32 bits per pixel
+const ID_32BPP: u16 = 0xB0C2;
+const ID_LATEST: u16 = 0xB0C5;
+
+// Only 32 bits per pixel, as 0x00RRGGBB
+pub type Pixel = u32;
+const BITS_PER_PIXEL: u16 = 32;
+
+#[derive(Debug)]
+pub struct BochsDisplay {
+    framebuffer_phys: u64,
+}
+
+impl BochsDisplay {
+    // Fails if the adapter is too old for 32 bits per pixel
+    pub fn new(bus: u8, slot: u8, header: PciHeaderType0) -> Option<Self> {
+        // https://wiki.osdev.org/PCI#Address_and_size_of_the_BAR
+        // BAR0 is memory, and if bits 1-2 are 0b10 it's 64 bits, with the top half in BAR1
+        let bar0 = header.base_addresses[0];
+        let mut framebuffer_phys = (bar0 & 0xFFFFFFF0) as u64;
+        if (bar0 >> 1) & 0b11 == 0b10 {
+            framebuffer_phys |= (header.base_addresses[1] as u64) << 32;
+        }
+
+        // Respond to memory accesses, in case the firmware didn't turn that on
+        pci_config_modify(bus, slot, 0, 0x1, |x| x | 0b10);
+
+        // Tell it the newest version we know, and it answers with what it can do
+        write_dispi(INDEX_ID, ID_LATEST);
+        if read_dispi(INDEX_ID) < ID_32BPP {
+            return None;
+        }
+
+        Some(Self { framebuffer_phys })
+    }
+
+    // Enough of the framebuffer for pages screens of width by height, one after another
+    pub fn framebuffer<'a>(
+        &self,
+        phys_alloc: &mut PhysAllocator,
+        width: usize,
+        height: usize,
+        pages: usize,
+    ) -> &'a mut [Volatile<Pixel>] {
+        phys_alloc.map_device_memory(self.framebuffer_phys, width * height * pages)
+    }
+
+    // Takes over the screen from the VGA. Room for pages screens is set aside
+    // below the one showing, for show_page. Fails if there isn't enough video memory,
+    // in which case the adapter is left disabled.
+    pub fn enable(&mut self, width: u16, height: u16, pages: u16) -> bool {
+        write_dispi(INDEX_ENABLE, 0);
+        write_dispi(INDEX_XRES, width);
+        write_dispi(INDEX_YRES, height);
+        write_dispi(INDEX_BPP, BITS_PER_PIXEL);
+        write_dispi(INDEX_VIRT_WIDTH, width);
+        write_dispi(INDEX_VIRT_HEIGHT, height * pages);
+        write_dispi(INDEX_X_OFFSET, 0);
+        write_dispi(INDEX_Y_OFFSET, 0);
+        write_dispi(INDEX_ENABLE, ENABLED | LFB_ENABLED);
+
+        // It shrinks the virtual height to fit, rather than complaining
+        if read_dispi(INDEX_VIRT_HEIGHT) < height * pages || read_dispi(INDEX_BPP) != BITS_PER_PIXEL
+        {
+            self.disable();
+            return false;
+        }
+        true
+    }
+
+    // Shows the page'th screen down, without copying anything
+    pub fn show_page(&mut self, page: u16) {
+        let height = read_dispi(INDEX_YRES);
+        write_dispi(INDEX_Y_OFFSET, height * page);
+    }
+
+    // Hands the screen back to the VGA, which won't be in text mode,
+    // see isa::vga::Vga::set_mode
+    pub fn disable(&mut self) {
+        write_dispi(INDEX_ENABLE, 0);
+    }
+}
+
+fn read_dispi(index: u16) -> u16 {
+    io_space_bar_write::<u16>(DISPI_INDEX, index);
+    io_space_bar_read::<u16>(DISPI_DATA)
+}
+
+fn write_dispi(index: u16, value: u16) {
+    io_space_bar_write::<u16>(DISPI_INDEX, index);
+    io_space_bar_write::<u16>(DISPI_DATA, value);
+}
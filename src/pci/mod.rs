@@ -1,9 +1,11 @@
 use audio_ac97::AudioAc97;
+use bochs_display::BochsDisplay;
 use headers::{parse_header_common, parse_header_type0};
 use io::pci_config_read_word;
 use pluggable_interrupt_os::println;
 
 pub mod audio_ac97;
+pub mod bochs_display;
 mod headers;
 mod io;
 
@@ -15,11 +17,13 @@ mod io;
 
 pub struct PciDevices {
     pub ac97: Option<AudioAc97>,
+    pub bochs_display: Option<BochsDisplay>,
     // We could add more devices here, if we wanted
 }
 
 pub fn scan_pci_devices() -> PciDevices {
     let mut audio = None;
+    let mut bochs_display = None;
 
     for bus in 0..=255 {
         for device in 0..32 {
@@ -42,10 +46,20 @@ pub fn scan_pci_devices() -> PciDevices {
                     }
 
                     audio = Some(AudioAc97::new(bus, device, full_header));
+                } else if headhead.header_type == 0x0
+                    && headhead.vendor_id == bochs_display::VENDOR_ID
+                    && headhead.device_id == bochs_display::DEVICE_ID
+                {
+                    // QEMU's -vga std, which is also the VGA at the usual ports
+                    let full_header = parse_header_type0(bus, device, 0, headhead);
+                    bochs_display = BochsDisplay::new(bus, device, full_header);
                 }
             }
         }
     }
 
-    PciDevices { ac97: audio }
+    PciDevices {
+        ac97: audio,
+        bochs_display,
+    }
 }
@@ -1,7 +1,17 @@
+use core::slice::from_raw_parts_mut;
+
 use bootloader::{
     bootinfo::{MemoryRegion, MemoryRegionType},
     BootInfo,
 };
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
 
 pub struct PhysAllocator {
     prime_region: MemoryRegion,
@@ -14,7 +24,7 @@ pub struct DualAddr {
     pub virt_addr: u64,
 }
 
-pub struct DualPtr32<'a, T> {
+pub struct DualPtr32<'a, T: ?Sized> {
     pub r_phys: u32,
     pub rw_virt: &'a mut T,
 }
@@ -46,13 +56,15 @@ impl PhysAllocator {
 
     // Aligns by 4
     pub fn get_hunk(&mut self, size: u64) -> DualAddr {
+        self.get_aligned_hunk(size, 4)
+    }
+
+    // align must be a power of two
+    fn get_aligned_hunk(&mut self, size: u64, align: u64) -> DualAddr {
+        debug_assert!(align.is_power_of_two());
         let phys_mem_end = self.prime_region.range.end_addr();
 
-        let mut phys_start = self.next_free_addr;
-        // align to 4 byte boundary
-        while phys_start % 4 != 0 {
-            phys_start += 1;
-        }
+        let phys_start = (self.next_free_addr + align - 1) & !(align - 1);
         // end should be exclusive
         let phys_end = phys_start + size;
 
@@ -84,6 +96,81 @@ impl PhysAllocator {
         }
     }
 
+    // Like alloc32, but for when we don't know how many we need until runtime
+    pub fn alloc32_slice<'a, T>(&mut self, len: usize) -> DualPtr32<'a, [T]> {
+        let DualAddr {
+            phys_addr,
+            virt_addr,
+        } = self.get_hunk((size_of::<T>() * len) as u64);
+
+        debug_assert!(phys_addr <= u32::MAX as u64);
+
+        DualPtr32 {
+            r_phys: phys_addr as u32,
+            rw_virt: unsafe { from_raw_parts_mut(virt_addr as *mut T, len) },
+        }
+    }
+
+    // The ISA DMA controller can only address the first 16MiB of memory,
+    // and a single transfer can't cross a 64KiB boundary.
+    // Aligning to 64KiB takes care of the second problem, as long as T fits in 64KiB.
+    // Fails if our free region has already been used up past 16MiB.
+    pub fn alloc_isa_dma<'a, T>(&mut self) -> Option<DualPtr32<'a, T>> {
+        const ISA_DMA_LIMIT: u64 = 16 * 1024 * 1024;
+        const ISA_DMA_BOUNDARY: u64 = 64 * 1024;
+        debug_assert!(size_of::<T>() as u64 <= ISA_DMA_BOUNDARY);
+
+        let phys_start = (self.next_free_addr + ISA_DMA_BOUNDARY - 1) & !(ISA_DMA_BOUNDARY - 1);
+        if phys_start + size_of::<T>() as u64 > ISA_DMA_LIMIT {
+            return None;
+        }
+
+        let DualAddr {
+            phys_addr,
+            virt_addr,
+        } = self.get_aligned_hunk(size_of::<T>() as u64, ISA_DMA_BOUNDARY);
+
+        Some(DualPtr32 {
+            r_phys: phys_addr as u32,
+            rw_virt: unsafe { &mut *(virt_addr as *mut T) },
+        })
+    }
+
+    // For memory that belongs to a device rather than to us, like the VGA's window
+    // at 0xA0000. The bootloader maps all of physical memory, so it's already there.
+    pub fn device_memory<'a, T>(&self, phys_addr: u64) -> &'a mut T {
+        unsafe { &mut *((phys_addr + self.physical_memory_offset) as *mut T) }
+    }
+
+    // Like device_memory, but for memory that may be beyond what the bootloader mapped,
+    // which only goes up to the end of the memory map. PCI BARs are often further up.
+    // Maps whatever isn't there yet, at the same offset as the rest of physical memory.
+    pub fn map_device_memory<'a, T>(&mut self, phys_addr: u64, len: usize) -> &'a mut [T] {
+        let offset = VirtAddr::new(self.physical_memory_offset);
+        let (level_4_frame, _) = Cr3::read();
+        let level_4_table = unsafe {
+            &mut *(offset + level_4_frame.start_address().as_u64()).as_mut_ptr::<PageTable>()
+        };
+        let mut mapper = unsafe { OffsetPageTable::new(level_4_table, offset) };
+
+        let size = (size_of::<T>() * len) as u64;
+        let first = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(phys_addr));
+        let last = PhysFrame::containing_address(PhysAddr::new(phys_addr + size - 1));
+        for frame in PhysFrame::range_inclusive(first, last) {
+            let page = Page::containing_address(offset + frame.start_address().as_u64());
+            if mapper.translate_addr(page.start_address()).is_none() {
+                let flags =
+                    PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+                match unsafe { mapper.map_to(page, frame, flags, self) } {
+                    Ok(flush) => flush.flush(),
+                    Err(e) => panic!("Failed to map device memory at {phys_addr:#X}: {e:?}"),
+                }
+            }
+        }
+
+        unsafe { from_raw_parts_mut((phys_addr + self.physical_memory_offset) as *mut T, len) }
+    }
+
     // MiB of free space
     pub fn mb_free(&self) -> u64 {
         self.kb_free() / 1024
@@ -101,3 +188,15 @@ impl PhysAllocator {
         phys_mem_end - self.next_free_addr
     }
 }
+
+// For the page tables map_device_memory needs
+unsafe impl FrameAllocator<Size4KiB> for PhysAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        const FRAME_SIZE: u64 = 4096;
+        if self.bytes_free() < 2 * FRAME_SIZE {
+            return None;
+        }
+        let DualAddr { phys_addr, .. } = self.get_aligned_hunk(FRAME_SIZE, FRAME_SIZE);
+        Some(PhysFrame::containing_address(PhysAddr::new(phys_addr)))
+    }
+}
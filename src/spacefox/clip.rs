@@ -0,0 +1,96 @@
+use super::math::Vec3f;
+
+// Cuts lines and triangles down to the part we can actually see, so anything partly
+// off screen (or partly behind us) still draws the bit that's on screen.
+// First against the near plane in camera space, since projecting a point
+// behind the camera flips it to the other side of the screen,
+// and then against the edges of the screen once the line is projected.
+// Filled triangles don't need that second step, as raster only fills
+// the cells that are on screen anyway.
+
+// Lines and triangles are cut off this far in front of the camera
+pub const NEAR: f32 = 0.5;
+
+// Keeps the part of a to b (in camera space) that's at least NEAR in front of the camera
+pub fn clip_near(a: Vec3f, b: Vec3f) -> Option<(Vec3f, Vec3f)> {
+    match (a.z >= NEAR, b.z >= NEAR) {
+        (true, true) => Some((a, b)),
+        (false, false) => None,
+        (a_in_front, _) => {
+            let t = (NEAR - a.z) / (b.z - a.z);
+            let cut = a + (b - a) * t;
+            if a_in_front {
+                Some((a, cut))
+            } else {
+                Some((cut, b))
+            }
+        }
+    }
+}
+
+// Keeps the part of a triangle (in camera space) that's at least NEAR in front
+// of the camera. Cutting a corner off a triangle leaves four corners, so this
+// returns up to four, in the same winding, and how many there are.
+// This is Sutherland-Hodgman with only one plane to clip against,
+// see https://en.wikipedia.org/wiki/Sutherland%E2%80%93Hodgman_algorithm
+pub fn clip_triangle_near(corners: [Vec3f; 3]) -> ([Vec3f; 4], usize) {
+    let mut out = [Vec3f::default(); 4];
+    let mut count = 0;
+    for i in 0..3 {
+        let a = corners[i];
+        let b = corners[(i + 1) % 3];
+        if a.z >= NEAR {
+            out[count] = a;
+            count += 1;
+        }
+        if (a.z >= NEAR) != (b.z >= NEAR) {
+            let t = (NEAR - a.z) / (b.z - a.z);
+            out[count] = a + (b - a) * t;
+            count += 1;
+        }
+    }
+    (out, count)
+}
+
+// Keeps the part of a to b that's inside 0..=max_x and 0..=max_y.
+// The points are a column and row on screen, with the depth in z.
+// This is Liang-Barsky, which finds how far along the line it enters and leaves
+// the screen in one pass, see https://en.wikipedia.org/wiki/Liang%E2%80%93Barsky_algorithm
+pub fn clip_screen(a: Vec3f, b: Vec3f, max_x: f32, max_y: f32) -> Option<(Vec3f, Vec3f)> {
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+    let mut enter: f32 = 0.0;
+    let mut leave: f32 = 1.0;
+
+    // For each edge, how fast the line heads out through it,
+    // and how far inside it the line starts
+    for (outwards, inside) in [(-dx, a.x), (dx, max_x - a.x), (-dy, a.y), (dy, max_y - a.y)] {
+        if outwards == 0.0 {
+            // Parallel to this edge, so it's all in or all out
+            if inside < 0.0 {
+                return None;
+            }
+        } else {
+            let t = inside / outwards;
+            if outwards < 0.0 {
+                enter = enter.max(t);
+            } else {
+                leave = leave.min(t);
+            }
+        }
+    }
+    if enter > leave {
+        return None;
+    }
+
+    // Depth isn't linear across the screen, but one over it is
+    let at = |t: f32| {
+        let inverse_depth = 1.0 / a.z + (1.0 / b.z - 1.0 / a.z) * t;
+        Vec3f {
+            x: a.x + dx * t,
+            y: a.y + dy * t,
+            z: 1.0 / inverse_depth,
+        }
+    };
+    Some((at(enter), at(leave)))
+}
@@ -0,0 +1,107 @@
+use crate::isa::vga::{Glyph, Vga};
+
+use super::render::FULL_BLOCK;
+
+// Our own characters, uploaded over code page 437's control character pictures
+// (smileys, card suits, arrows) which nothing here uses. They draw like any other
+// character, in text mode and in the graphics modes' text.
+
+const FIRST_SPRITE: u8 = 0x01;
+pub const SHIP: char = '\u{01}';
+// Nothing in the game fires yet, but the glyph is there for when it does
+#[allow(dead_code)]
+pub const LASER: char = '\u{02}';
+pub const EXPLOSION: char = '\u{03}';
+pub const TOWER: char = '\u{04}';
+pub const PAUSE: char = '\u{05}';
+
+const SPRITES: [Glyph; 5] = [
+    // SHIP, from behind
+    [
+        0b00000000, 0b00000000, 0b00000000, 0b00011000, 0b00011000, 0b00111100, 0b00111100,
+        0b01111110, 0b11111111, 0b11111111, 0b11011011, 0b10011001, 0b00011000, 0b00111100,
+        0b00000000, 0b00000000,
+    ],
+    // LASER
+    [
+        0b00000000, 0b00011000, 0b00011000, 0b00011000, 0b00011000, 0b00011000, 0b00011000,
+        0b00011000, 0b00011000, 0b00011000, 0b00011000, 0b00011000, 0b00011000, 0b00011000,
+        0b00011000, 0b00000000,
+    ],
+    // EXPLOSION
+    [
+        0b00000000, 0b00000000, 0b10001001, 0b01001010, 0b00101100, 0b00011000, 0b11111111,
+        0b00011000, 0b00110100, 0b01010010, 0b10010001, 0b00001000, 0b00000000, 0b00000000,
+        0b00000000, 0b00000000,
+    ],
+    // TOWER
+    [
+        0b00000000, 0b00000000, 0b00111100, 0b00111100, 0b00100100, 0b00111100, 0b00100100,
+        0b00111100, 0b00100100, 0b00111100, 0b00100100, 0b00111100, 0b01111110, 0b00000000,
+        0b00000000, 0b00000000,
+    ],
+    // PAUSE
+    [
+        0b00000000, 0b00000000, 0b00000000, 0b01100110, 0b01100110, 0b01100110, 0b01100110,
+        0b01100110, 0b01100110, 0b01100110, 0b01100110, 0b01100110, 0b00000000, 0b00000000,
+        0b00000000, 0b00000000,
+    ],
+];
+
+// Each quarter of a cell on or off, for drawing at twice text mode's resolution.
+// Empty and full are a space and FULL_BLOCK, so only the 14 in between are ours.
+const FIRST_QUADRANT: u8 = 0x10;
+const QUADRANTS: [Glyph; 14] = {
+    let mut glyphs = [[0; 16]; 14];
+    let mut i = 0;
+    while i < glyphs.len() {
+        glyphs[i] = quadrant_glyph(i as u8 + 1);
+        i += 1;
+    }
+    glyphs
+};
+
+// Puts our glyphs into the VGA's font, and makes characters 8 pixels wide
+// so the quadrants meet up
+pub fn upload(vga: &mut Vga) {
+    vga.set_8_dot_characters();
+    vga.set_glyphs(FIRST_SPRITE, &SPRITES);
+    vga.set_glyphs(FIRST_QUADRANT, &QUADRANTS);
+}
+
+// Which quarters of a cell are filled in, for quadrants
+pub const TOP_LEFT: u8 = 0b1000;
+pub const TOP_RIGHT: u8 = 0b0100;
+pub const BOTTOM_LEFT: u8 = 0b0010;
+pub const BOTTOM_RIGHT: u8 = 0b0001;
+pub const WHOLE_CELL: u8 = TOP_LEFT | TOP_RIGHT | BOTTOM_LEFT | BOTTOM_RIGHT;
+
+// The character with the given quarters filled in
+pub fn quadrants(bits: u8) -> char {
+    match bits & WHOLE_CELL {
+        0 => ' ',
+        WHOLE_CELL => FULL_BLOCK,
+        bits => (FIRST_QUADRANT + bits - 1) as char,
+    }
+}
+
+const fn quadrant_glyph(bits: u8) -> Glyph {
+    let top = half(bits & TOP_LEFT != 0, 0xF0) | half(bits & TOP_RIGHT != 0, 0x0F);
+    let bottom = half(bits & BOTTOM_LEFT != 0, 0xF0) | half(bits & BOTTOM_RIGHT != 0, 0x0F);
+    let mut glyph = [0; 16];
+    let mut row = 0;
+    while row < glyph.len() {
+        glyph[row] = if row < glyph.len() / 2 { top } else { bottom };
+        row += 1;
+    }
+    glyph
+}
+
+// Half a row of pixels, if it's on
+const fn half(on: bool, pixels: u8) -> u8 {
+    if on {
+        pixels
+    } else {
+        0
+    }
+}
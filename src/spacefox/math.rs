@@ -0,0 +1,258 @@
+use core::ops::{Add, Mul, Neg, Sub};
+
+use crate::trig::{sin_cos, sqrt};
+
+// Just enough linear algebra for the renderer. Coordinates are left handed,
+// like the rest of the game: +x is right, +y is up, and +z is into the screen.
+// Angles are in radians, and a positive angle turns +x towards +y,
+// +y towards +z, and +z towards +x, depending on the axis.
+// See https://www.songho.ca/opengl/gl_transform.html for the matrices
+// and https://www.3dgep.com/understanding-quaternions/ for the quaternions.
+
+pub const fn v(value: [f32; 3]) -> Vec3f {
+    Vec3f {
+        x: value[0],
+        y: value[1],
+        z: value[2],
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Vec3f {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3f {
+    pub const ONE: Self = v([1.0, 1.0, 1.0]);
+
+    pub const fn mirx(&self) -> Self {
+        Self {
+            x: -self.x,
+            y: self.y,
+            z: self.z,
+        }
+    }
+
+    pub fn dot(self, other: Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    // Points the way your thumb does when your fingers curl from self to other,
+    // on your left hand since the coordinates are left handed
+    pub fn cross(self, other: Self) -> Self {
+        v([
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        ])
+    }
+
+    pub fn length(self) -> f32 {
+        sqrt(self.dot(self))
+    }
+
+    // The same direction but 1 long, or all zeros if self is
+    pub fn normalized(self) -> Self {
+        let length = self.length();
+        if length == 0.0 {
+            self
+        } else {
+            self * (1.0 / length)
+        }
+    }
+}
+
+impl Add for Vec3f {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        v([self.x + other.x, self.y + other.y, self.z + other.z])
+    }
+}
+
+impl Sub for Vec3f {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        v([self.x - other.x, self.y - other.y, self.z - other.z])
+    }
+}
+
+impl Mul<f32> for Vec3f {
+    type Output = Self;
+
+    fn mul(self, scale: f32) -> Self {
+        v([self.x * scale, self.y * scale, self.z * scale])
+    }
+}
+
+impl Neg for Vec3f {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        v([-self.x, -self.y, -self.z])
+    }
+}
+
+// A rotation. Unlike Euler angles these combine without gimbal lock,
+// and they're cheap to turn into a matrix once per model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quat {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    // axis has to be normalized
+    pub fn from_axis_angle(axis: Vec3f, angle: f32) -> Self {
+        let (sin, cos) = sin_cos(angle / 2.0);
+        Self {
+            w: cos,
+            x: axis.x * sin,
+            y: axis.y * sin,
+            z: axis.z * sin,
+        }
+    }
+
+    // Rolls around z, then pitches around x, then turns around y,
+    // which is the order a plane's controls make sense in
+    pub fn from_euler(yaw: f32, pitch: f32, roll: f32) -> Self {
+        let yaw = Self::from_axis_angle(v([0.0, 1.0, 0.0]), yaw);
+        let pitch = Self::from_axis_angle(v([1.0, 0.0, 0.0]), pitch);
+        let roll = Self::from_axis_angle(v([0.0, 0.0, 1.0]), roll);
+        yaw * pitch * roll
+    }
+
+    // The opposite rotation, as long as this one is normalized
+    pub fn conjugate(self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+// Applies other first, then self
+impl Mul for Quat {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        }
+    }
+}
+
+// Row major, and it multiplies column vectors, so translation is the last column
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Self = Self {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn translation(offset: Vec3f) -> Self {
+        let mut m = Self::IDENTITY;
+        m.m[0][3] = offset.x;
+        m.m[1][3] = offset.y;
+        m.m[2][3] = offset.z;
+        m
+    }
+
+    pub fn scale(scale: Vec3f) -> Self {
+        let mut m = Self::IDENTITY;
+        m.m[0][0] = scale.x;
+        m.m[1][1] = scale.y;
+        m.m[2][2] = scale.z;
+        m
+    }
+
+    // q has to be normalized
+    pub fn rotation(q: Quat) -> Self {
+        let Quat { w, x, y, z } = q;
+        Self {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // Scales, then rotates, then moves, which is how a model gets into the world
+    pub fn from_scale_rotation_translation(scale: Vec3f, rotation: Quat, offset: Vec3f) -> Self {
+        Self::translation(offset) * Self::rotation(rotation) * Self::scale(scale)
+    }
+
+    // Only for affine matrices, so w is always 1 and there's nothing to divide by.
+    // Perspective is done separately, see Camera::project.
+    pub fn transform_point(&self, p: Vec3f) -> Vec3f {
+        let m = &self.m;
+        v([
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        ])
+    }
+}
+
+// Applies other first, then self
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (row, out) in m.iter_mut().enumerate() {
+            for (col, out) in out.iter_mut().enumerate() {
+                *out = (0..4).map(|i| self.m[row][i] * other.m[i][col]).sum();
+            }
+        }
+        Self { m }
+    }
+}
@@ -1,53 +1,117 @@
 use crate::{
-    pci::audio_ac97::{music_loop::MusicLoop, AudioAc97},
+    audio::{
+        analyser::{Analyser, Levels, BANDS, WINDOW_FRAMES},
+        mixer::{Mixer, VoiceHandle},
+        playlist::{Playlist, Transition},
+        spatial::{Emitter, Placement},
+        MusicOutput,
+    },
+    pci::{audio_ac97::music_loop::PlaybackStats, PciDevices},
     phys_alloc::PhysAllocator,
+    trig::sin_cos,
 };
-use music_data::WAV_DATA_SAMPLES;
-use pc_keyboard::DecodedKey;
+use math::{v, Mat4, Quat, Vec3f};
+use music_data::{GAME_BEATS, GAME_MUSIC, GAME_OVER_STINGER, MENU_MUSIC, SPEAKER_THEME};
+use pc_keyboard::{DecodedKey, KeyCode};
 use pluggable_interrupt_os::{
     println,
-    vga_buffer::{
-        clear_screen, plot, plot_num_right_justified, Color, ColorCode, BUFFER_HEIGHT, BUFFER_WIDTH,
-    },
+    vga_buffer::{clear_screen, plot, Color, ColorCode, BUFFER_HEIGHT},
 };
+use raster::Raster;
+use render::{
+    high_res::HighRes, mode_13h::Mode13h, text_mode::TextMode, Dimensions, Renderer, FULL_BLOCK,
+    LEFT_HALF_BLOCK, LOWER_HALF_BLOCK, MIDDLE_DOT,
+};
+use rhythm::Rhythm;
+use sfx_data::{EXPLOSION, MENU_CLICK, NEAR_MISS, SCORE_BLIP, TOWER_WHOOSH};
 
+mod clip;
+mod glyphs;
+mod math;
 mod music_data;
+mod raster;
+mod render;
+mod rhythm;
+mod sfx_data;
 
-type Line = [i8; 7];
-type LineBank = [Line; 100];
+// Column, row and depth of each end, then 1 for a tower or 0 for the ship,
+// then which model in the world it's from, or -1 if nothing can hide it.
+// Columns and rows are in sixteenths, so the line knows where it is in a cell.
+type Line = [i16; 8];
+const LINE_SUBCELLS: f32 = 16.0;
 
-const fn v(value: [f32; 3]) -> Vec3f {
-    Vec3f {
-        x: value[0],
-        y: value[1],
-        z: value[2],
-    }
-}
+type World = [Model; 30];
 
-#[derive(Default, Clone, Copy)]
-struct Vec3f {
-    x: f32,
-    y: f32,
-    z: f32,
+#[derive(Clone, Copy)]
+struct Model {
+    prims: &'static [Prim],
+    pos: Vec3f,
+    rotation: Quat,
+    // Along the model's own axes, before it's rotated
+    scale: Vec3f,
+    // Skips faces pointing away from the camera, which only works
+    // for closed models whose prims all wind the same way, see Prim
+    cull_back_faces: bool,
+    // Hides the parts of its wireframe that are behind other models
+    hidden_lines: bool,
 }
 
-impl Vec3f {
-    const fn mirx(&self) -> Self {
+impl Default for Model {
+    fn default() -> Self {
         Self {
-            x: -self.x,
-            y: self.y,
-            z: self.z,
+            prims: &[],
+            pos: Vec3f::default(),
+            rotation: Quat::IDENTITY,
+            scale: Vec3f::ONE,
+            cull_back_faces: false,
+            hidden_lines: false,
         }
     }
 }
 
-type World = [Model; 30];
+impl Model {
+    // From the model's own coordinates into the world
+    fn transform(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.pos)
+    }
+}
 
-#[derive(Default, Clone, Copy)]
-struct Model {
-    prims: &'static [Prim],
+// What the player sees the world through
+#[derive(Clone, Copy)]
+struct Camera {
     pos: Vec3f,
-    scale: f32,
+    // Looking down +z, with +y up, when this is Quat::IDENTITY
+    orientation: Quat,
+    // How much of the world fits across the screen, in radians.
+    // Text cells are about twice as tall as they are wide, so the same angle
+    // fits across the 80 columns and down the 25 rows.
+    fov: f32,
+}
+
+// 2 * atan(1/2), which is what the game always had before there was a camera
+const DEFAULT_FOV: f32 = 0.9273;
+
+impl Camera {
+    // From the world into the camera's coordinates, which is
+    // undoing the camera's own move and then its rotation
+    fn view(&self) -> Mat4 {
+        Mat4::rotation(self.orientation.conjugate()) * Mat4::translation(-self.pos)
+    }
+
+    // From the camera's coordinates to across and down a screen width by height,
+    // with the depth in z. p has to be in front of the camera, see clip::clip_near.
+    fn project(&self, p: Vec3f, width: usize, height: usize) -> Vec3f {
+        let xoff = width as f32 / 2.0;
+        let yoff = height as f32 / 2.0;
+
+        let (sin, cos) = sin_cos(self.fov / 2.0);
+        let focal = cos / sin;
+        v([
+            p.x / p.z * focal * xoff + xoff,
+            -p.y / p.z * focal * yoff + yoff,
+            p.z,
+        ])
+    }
 }
 
 const SHIPV1: Vec3f = v([-1.361523, -0.532862, -6.396634]);
@@ -75,8 +139,12 @@ const BLOCKF1: Prim = Prim::Quad(BLOCKV25, BLOCKV26, BLOCKV28, BLOCKV27);
 const BLOCKF2: Prim = Prim::Quad(BLOCKV31, BLOCKV32, BLOCKV30, BLOCKV29);
 const BLOCKF3: Prim = Prim::Quad(BLOCKV29, BLOCKV30, BLOCKV26, BLOCKV25);
 const BLOCKF4: Prim = Prim::Quad(BLOCKV32, BLOCKV28, BLOCKV26, BLOCKV30);
-const BLOCK_QUADS: &[Prim] = &[BLOCKF1, BLOCKF2, BLOCKF3, BLOCKF4];
+// The side facing the ship, which a wireframe got away without
+const BLOCKF5: Prim = Prim::Quad(BLOCKV27, BLOCKV28, BLOCKV32, BLOCKV31);
+const BLOCK_QUADS: &[Prim] = &[BLOCKF1, BLOCKF2, BLOCKF3, BLOCKF4, BLOCKF5];
 
+// Corners go anticlockwise when you look at the front of a face (with +y up),
+// so (p2 - p1) x (p3 - p1) points into the model, see faces_camera
 #[derive(Default, Clone, Copy)]
 enum Prim {
     #[default]
@@ -86,44 +154,190 @@ enum Prim {
 }
 
 pub struct Game<'a> {
-    music: MusicLoop<'a>,
+    music: MusicOutput<'a>,
+    mixer: Mixer<'a>,
     music_started: bool,
+    show_audio_stats: bool,
+    analyser: Analyser,
+    show_visualiser: bool,
+    // Where the game and its overlays are drawn, in text mode
+    screen: TextMode,
+    // or in graphics, picked with F5
+    mode_13h: Mode13h<'a>,
+    high_res: Option<HighRes<'a>>,
+    display: Display,
+    // What's on screen right now, which is always text outside the game
+    showing: Display,
     state: GameState,
     random: u64,
     high_score: u64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Display {
+    Text,
+    Mode13h,
+    HighRes,
+}
+
 enum GameState {
-    Menu { first_draw: bool, need_start: bool },
+    Menu {
+        first_draw: bool,
+        need_start: bool,
+        // Start in rhythm mode, see spacefox::rhythm
+        rhythm: bool,
+    },
     SpaceFox(SpaceFox),
-    GameOver { first_draw: bool, timer: u16 },
+    GameOver {
+        first_draw: bool,
+        timer: u16,
+    },
 }
 
 pub struct SpaceFox {
-    b: usize,
-    lines: [LineBank; 2],
-    end: [usize; 2],
+    // Filled in triangles when solid is on, and otherwise
+    // only their depth, for hiding lines behind them
+    raster: Raster,
+    solid: bool,
     world: World,
     xvel: f32,
     yvel: f32,
     score: u64,
+    // How far the tower moved relative to the ship last update, for Doppler
+    tower_motion: Vec3f,
+    tower_whoosh: Option<VoiceHandle>,
+    // Squared distance of the tower's closest pass so far
+    closest_pass: f32,
+    // Extra intensity from the last near miss, wearing off each update
+    near_miss_boost: u8,
+    paused: bool,
+    // Set in rhythm mode, which times the towers to the music
+    rhythm: Option<Rhythm>,
+    camera: Camera,
+    // How far the ship is banked and pitched right now, in radians
+    roll: f32,
+    pitch: f32,
 }
 
+// The PIT's default rate, which is how often update() runs
+const TICKS_PER_SECOND: f32 = 18.2;
+// Tower edges nearer than this are drawn brighter
+const NEAR_TOWER_DEPTH: f32 = 30.0;
+// How close a tower gets, in front of the ship, before we hear it
+const WHOOSH_DISTANCE: f32 = 60.0;
+// A tower passing closer than this is a near miss, which bumps the
+// intensity by NEAR_MISS_BOOST for as many updates (~3 seconds)
+const NEAR_MISS_DISTANCE: f32 = 2.5;
+const NEAR_MISS_BOOST: u8 = 64;
+// The music speeds up by this much for every tower passed, up to
+// MAX_MUSIC_RATE, and drops to GAME_OVER_MUSIC_RATE after a crash
+const MUSIC_RATE_PER_POINT: f32 = 0.01;
+const MAX_MUSIC_RATE: f32 = 1.25;
+const GAME_OVER_MUSIC_RATE: f32 = 0.55;
+// Where towers appear
+const HORIZON: f32 = 200.0;
+// In rhythm mode towers come in at a steady speed, in world units per second,
+// and in one of three lanes this far apart
+const RHYTHM_TOWER_SPEED: f32 = 100.0;
+const RHYTHM_LANE_WIDTH: f32 = 3.0;
+
+const XSPEED: f32 = 0.7;
+const SHIP_START: Vec3f = v([0.0, -3.0, 15.0]);
+// How far the ship leans into turns and climbs at full speed, in radians,
+// and how much of the way there it gets each update
+const MAX_ROLL: f32 = 0.6;
+const MAX_PITCH: f32 = 0.3;
+const YAW_PER_ROLL: f32 = 0.3;
+const LEAN_EASE: f32 = 0.3;
+// The camera follows the ship this much of the way from where it started,
+// and leans with it this much
+const CAMERA_FOLLOW: f32 = 0.5;
+const CAMERA_ROLL: f32 = 0.25;
+
 const PLAYER: usize = 1;
 const BLOCK: usize = 0;
 
 impl<'a> Game<'a> {
-    pub fn new(phys_alloc: &mut PhysAllocator, ac97: AudioAc97) -> Self {
-        let music = MusicLoop::new(phys_alloc, &WAV_DATA_SAMPLES, ac97);
-        Self {
+    pub fn new(phys_alloc: &mut PhysAllocator, devs: PciDevices) -> Self {
+        let music = MusicOutput::new(phys_alloc, devs.ac97, SPEAKER_THEME);
+        let mut mode_13h = Mode13h::new(phys_alloc);
+        // Before the high resolution mode copies the font
+        glyphs::upload(mode_13h.vga());
+        let high_res = devs
+            .bochs_display
+            .map(|display| HighRes::new(phys_alloc, display, mode_13h.vga()));
+        let mut game = Self {
             music,
+            mixer: Mixer::new(Playlist::new()),
             music_started: false,
+            show_audio_stats: false,
+            analyser: Analyser::new(),
+            show_visualiser: false,
+            screen: TextMode::new(),
+            mode_13h,
+            high_res,
+            display: Display::Text,
+            showing: Display::Text,
             state: GameState::Menu {
                 first_draw: true,
                 need_start: false,
+                rhythm: false,
             },
             random: 0xDEADBEEF,
             high_score: 0,
+        };
+        game.cue_music();
+        game
+    }
+
+    // Every state has its own music, call this whenever state changes
+    fn cue_music(&mut self) {
+        let playlist = self.mixer.music();
+        playlist.set_intensity(match &self.state {
+            GameState::SpaceFox(space_fox) => space_fox.intensity(),
+            _ => 0,
+        });
+        let result = match self.state {
+            GameState::Menu { .. } => {
+                playlist.change(Some(&MENU_MUSIC), Transition::Crossfade { ms: 1500 })
+            }
+            GameState::SpaceFox(_) => {
+                playlist.change(Some(&GAME_MUSIC), Transition::Crossfade { ms: 300 })
+            }
+            GameState::GameOver { .. } => {
+                playlist.change(None, Transition::Stinger(&GAME_OVER_STINGER))
+            }
+        };
+        if let Err(e) = result {
+            panic!("Couldn't load music: {e:?}");
+        }
+        self.apply_music_effects();
+    }
+
+    // Muffles the music while paused, and mangles and slows it after a crash
+    fn apply_music_effects(&mut self) {
+        match self.state {
+            GameState::SpaceFox(ref space_fox) if space_fox.paused => {
+                let effects = self.mixer.music_effects();
+                effects.clear(300);
+                effects.set_lowpass(500, 300);
+            }
+            GameState::SpaceFox(ref space_fox) => {
+                self.mixer.music_effects().clear(600);
+                self.mixer.set_playback_rate(space_fox.music_rate(), 300);
+            }
+            GameState::GameOver { .. } => {
+                let effects = self.mixer.music_effects();
+                effects.set_lowpass(2500, 200);
+                effects.set_bitcrush(6, 3, 160, 200);
+                effects.set_echo(250, 120, 110, 200);
+                // Winds down like a tape deck losing power
+                self.mixer.set_playback_rate(GAME_OVER_MUSIC_RATE, 1200);
+            }
+            GameState::Menu { .. } => {
+                self.mixer.music_effects().clear(600);
+                self.mixer.set_playback_rate(1.0, 1000);
+            }
         }
     }
 
@@ -133,15 +347,35 @@ impl<'a> Game<'a> {
         return (self.random % 256) as u8;
     }
 
+    // called on every pass through the cpu loop, not just on ticks
+    pub fn poll(&mut self) {
+        if self.music_started {
+            self.music.poll(&mut self.mixer);
+        }
+    }
+
     pub fn tick(&mut self) {
         if self.music_started {
-            self.music.wind();
+            self.music.wind(&mut self.mixer);
+        } else {
+            self.music.play(&mut self.mixer);
+            self.music_started = true;
         }
         let r = self.rand();
+        // What the player is hearing right now, for rhythm mode
+        let frames_queued = self.music.frames_queued().unwrap_or(0);
+        let music_position = self.mixer.music_position(&GAME_MUSIC, frames_queued);
+        // The menu and game over are printed as text
+        let display = match self.state {
+            GameState::SpaceFox(_) => self.display,
+            _ => Display::Text,
+        };
+        self.show(display);
         match self.state {
             GameState::Menu {
                 ref mut first_draw,
-                ref mut need_start,
+                need_start,
+                rhythm,
             } => {
                 if *first_draw {
                     clear_screen();
@@ -165,36 +399,82 @@ impl<'a> Game<'a> {
                     println!();
                     println!();
                     println!("    SpaceFox x86_64");
+                    // println! can't print our own glyphs, so the ship goes in
+                    // after it, on the line that's just scrolled up
+                    plot(
+                        glyphs::SHIP,
+                        2,
+                        BUFFER_HEIGHT - 2,
+                        ColorCode::new(Color::LightCyan, Color::Black),
+                    );
                     println!();
                     println!("        Use WASD to move, and Space to brake");
+                    println!("        F switches to solid shapes, and back");
+                    println!("        F5 switches between text and graphics modes");
                     println!("        Watch out for the red obstacles");
                     println!();
                     println!("        Press any key to play!");
+                    if self.music.frames_queued().is_some() {
+                        println!("        Or R for rhythm mode, where the towers come on the beat");
+                    }
                     println!();
                     println!();
                     println!("    High Score: {}", self.high_score);
                     println!();
                     *first_draw = false;
                 }
-                if *need_start {
-                    clear_screen();
-                    if !self.music_started {
-                        self.music.play();
-                        self.music_started = true;
-                    }
-                    self.state = GameState::SpaceFox(SpaceFox::new());
+                if need_start {
+                    // The menu was printed straight to the screen
+                    self.screen.forget_screen();
+                    let rhythm = rhythm.then(|| Rhythm::new(&GAME_BEATS, GAME_MUSIC.ending));
+                    self.state = GameState::SpaceFox(SpaceFox::new(rhythm));
+                    self.cue_music();
                 }
             }
             GameState::SpaceFox(ref mut space_fox) => {
-                if space_fox.update(r) {
-                    space_fox.draw();
-                } else {
-                    self.high_score = self.high_score.max(space_fox.score);
-                    self.state = GameState::GameOver {
-                        first_draw: true,
-                        timer: 50,
-                    };
+                if !space_fox.paused {
+                    let score = space_fox.score;
+                    let near_miss_boost = space_fox.near_miss_boost;
+                    if !space_fox.update(r, music_position) {
+                        let tower = Placement::from_emitter(&space_fox.tower_emitter());
+                        self.mixer.play_sfx_at(&EXPLOSION, tower);
+                        self.high_score = self.high_score.max(space_fox.score);
+                        self.state = GameState::GameOver {
+                            first_draw: true,
+                            timer: 50,
+                        };
+                        self.cue_music();
+                        return;
+                    }
+                    if space_fox.score > score {
+                        self.mixer.play_sfx(&SCORE_BLIP);
+                    }
+                    // The boost only ever goes back up for a new near miss
+                    if space_fox.near_miss_boost > near_miss_boost {
+                        self.mixer.play_sfx(&NEAR_MISS);
+                    }
+                    place_tower_whoosh(&mut self.mixer, space_fox);
+                    self.mixer.music().set_intensity(space_fox.intensity());
+                    self.mixer.set_playback_rate(space_fox.music_rate(), 1000);
+                }
+
+                let screen: &mut dyn Renderer = match (self.showing, &mut self.high_res) {
+                    (Display::Mode13h, _) => &mut self.mode_13h,
+                    (Display::HighRes, Some(high_res)) => high_res,
+                    _ => &mut self.screen,
+                };
+                screen.clear();
+                space_fox.draw(screen);
+                if let Some(stats) = self.music.stats().filter(|_| self.show_audio_stats) {
+                    draw_audio_stats(screen, &stats);
+                }
+                // Straight from the sound card, so flat bars mean
+                // nothing is reaching the speakers
+                let mut frames = [[0, 0]; WINDOW_FRAMES];
+                if self.show_visualiser && self.music.recent_frames(&mut frames) {
+                    draw_visualiser(screen, &self.analyser.update(&frames));
                 }
+                screen.present();
             }
             GameState::GameOver {
                 ref mut first_draw,
@@ -213,6 +493,12 @@ impl<'a> Game<'a> {
                     println!();
                     println!();
                     println!("        Game Over! You hit a tower!");
+                    plot(
+                        glyphs::EXPLOSION,
+                        6,
+                        BUFFER_HEIGHT - 2,
+                        ColorCode::new(Color::LightRed, Color::Black),
+                    );
                     println!();
                     println!();
                     println!();
@@ -226,9 +512,48 @@ impl<'a> Game<'a> {
                     self.state = GameState::Menu {
                         first_draw: true,
                         need_start: false,
+                        rhythm: false,
                     };
+                    self.cue_music();
+                }
+            }
+        }
+    }
+
+    // Switches modes through text, as each graphics mode knows how to get back to it
+    fn show(&mut self, display: Display) {
+        if display == self.showing {
+            return;
+        }
+        match (self.showing, &mut self.high_res) {
+            (Display::Mode13h, _) => self.mode_13h.leave(),
+            (Display::HighRes, Some(high_res)) => high_res.leave(self.mode_13h.vga()),
+            _ => {}
+        }
+        // The text screen was wiped, or printed over by the menu
+        self.screen.forget_screen();
+        self.showing = match display {
+            Display::Text => Display::Text,
+            Display::Mode13h => {
+                self.mode_13h.enter();
+                Display::Mode13h
+            }
+            Display::HighRes => {
+                let vga = self.mode_13h.vga();
+                let entered = self
+                    .high_res
+                    .as_mut()
+                    .is_some_and(|high_res| high_res.enter(vga));
+                if entered {
+                    Display::HighRes
+                } else {
+                    Display::Text
                 }
             }
+        };
+        // Don't try again every tick if the mode didn't work
+        if display != Display::Text {
+            self.display = self.showing;
         }
     }
 
@@ -248,71 +573,250 @@ impl<'a> Game<'a> {
                     .wrapping_add(c as u64);
             }
         }
+        if k == DecodedKey::RawKey(KeyCode::F3) {
+            self.show_audio_stats = !self.show_audio_stats;
+            return;
+        }
+        if k == DecodedKey::RawKey(KeyCode::F4) {
+            self.show_visualiser = !self.show_visualiser;
+            return;
+        }
+        if k == DecodedKey::RawKey(KeyCode::F5) {
+            self.display = match self.display {
+                Display::Text => Display::Mode13h,
+                Display::Mode13h if self.high_res.is_some() => Display::HighRes,
+                _ => Display::Text,
+            };
+            return;
+        }
         match self.state {
             GameState::Menu {
-                ref mut need_start, ..
-            } => *need_start = true,
-            GameState::SpaceFox(ref mut space_fox) => space_fox.key(k),
+                ref mut need_start,
+                ref mut rhythm,
+                ..
+            } => {
+                if !*need_start {
+                    self.mixer.play_sfx(&MENU_CLICK);
+                    // Rhythm mode needs to hear where the music is
+                    *rhythm = k == DecodedKey::Unicode('r') && self.music.frames_queued().is_some();
+                }
+                *need_start = true;
+            }
+            GameState::SpaceFox(ref mut space_fox) => {
+                let paused = space_fox.paused;
+                space_fox.key(k);
+                if space_fox.paused != paused {
+                    self.apply_music_effects();
+                }
+            }
             GameState::GameOver { .. } => {}
         }
     }
 }
 
+// Starts the whoosh as each tower gets close, and keeps it following the tower
+fn place_tower_whoosh(mixer: &mut Mixer, space_fox: &mut SpaceFox) {
+    let emitter = space_fox.tower_emitter();
+    let placement = Placement::from_emitter(&emitter);
+
+    if let Some(whoosh) = space_fox.tower_whoosh {
+        if mixer.place(whoosh, placement) {
+            return;
+        }
+        space_fox.tower_whoosh = None;
+    }
+
+    // Only as it crosses the line, so it starts once per tower
+    let z = emitter.offset[2];
+    let z_before = z - space_fox.tower_motion.z;
+    if z < WHOOSH_DISTANCE && z_before >= WHOOSH_DISTANCE {
+        space_fox.tower_whoosh = Some(mixer.play_sfx_at(&TOWER_WHOOSH, placement));
+    }
+}
+
 const GRAD_HOR: &[u8] = "#==----==#".as_bytes();
 
-impl SpaceFox {
-    pub fn new() -> Self {
-        for x in 0..BUFFER_WIDTH {
-            let c = GRAD_HOR[x / 8];
-            plot(
-                c as char,
-                x,
-                12,
-                ColorCode::new(Color::LightGray, Color::Black),
-            );
+// Debug overlay for the sound card, toggled with F3
+const AUDIO_STATS_LABELS: [&str; 5] = [
+    "underruns   ",
+    "last valid  ",
+    "fifo errors ",
+    "refills     ",
+    "headroom ms ",
+];
+const AUDIO_STATS_NUM_WIDTH: usize = 8;
+
+fn draw_audio_stats(screen: &mut dyn Renderer, stats: &PlaybackStats) {
+    let color = ColorCode::new(Color::LightGreen, Color::Black);
+    let values = [
+        stats.underruns,
+        stats.last_valid_reached,
+        stats.fifo_errors,
+        stats.refills,
+        stats.worst_headroom_ms(),
+    ];
+    for (row, (label, value)) in AUDIO_STATS_LABELS.iter().zip(values).enumerate() {
+        let col = screen.text(label, 0, row, color);
+        screen.text_num_right_justified(AUDIO_STATS_NUM_WIDTH, value as isize, col, row, color);
+    }
+}
+
+// VU meters and spectrum bars in the bottom right corner, toggled with F4.
+// The bars are 2 columns wide with a gap, and the meters run underneath them.
+const SPECTRUM_ROWS: usize = 4;
+const VISUALISER_WIDTH: usize = BANDS * 3;
+const VISUALISER_HEIGHT: usize = SPECTRUM_ROWS + 2;
+const VU_LABELS: [char; 2] = ['L', 'R'];
+const VU_WIDTH: usize = VISUALISER_WIDTH - 2;
+
+// Green, then yellow, then red towards the top of the scale
+fn level_color(fraction: f32) -> ColorCode {
+    let color = if fraction >= 0.875 {
+        Color::LightRed
+    } else if fraction >= 0.7 {
+        Color::Yellow
+    } else {
+        Color::LightGreen
+    };
+    ColorCode::new(color, Color::Black)
+}
+
+fn draw_visualiser(screen: &mut dyn Renderer, levels: &Levels) {
+    let dimensions = screen.dimensions();
+    let left = dimensions.columns.saturating_sub(VISUALISER_WIDTH);
+    let top = dimensions.rows.saturating_sub(VISUALISER_HEIGHT);
+    // Everything is in half cells, so a level picks a full or half block for the end
+    let half_cells = |level: u8, cells: usize| level as usize * (cells * 2 + 1) / 256;
+
+    for (band, &level) in levels.bands.iter().enumerate() {
+        let height = half_cells(level, SPECTRUM_ROWS);
+        for row in 0..SPECTRUM_ROWS {
+            let c = match height.saturating_sub(row * 2) {
+                0 => ' ',
+                1 => LOWER_HALF_BLOCK,
+                _ => FULL_BLOCK,
+            };
+            let color = level_color(row as f32 / SPECTRUM_ROWS as f32);
+            let screen_row = top + SPECTRUM_ROWS - 1 - row;
+            for col in 0..2 {
+                screen.text_char(c, left + band * 3 + col, screen_row, color);
+            }
         }
+    }
 
+    for (channel, &level) in levels.channels.iter().enumerate() {
+        let row = top + SPECTRUM_ROWS + channel;
+        let label_color = ColorCode::new(Color::LightGray, Color::Black);
+        screen.text_char(VU_LABELS[channel], left, row, label_color);
+        let length = half_cells(level, VU_WIDTH);
+        for cell in 0..VU_WIDTH {
+            let (c, color) = match length.saturating_sub(cell * 2) {
+                0 => (MIDDLE_DOT, ColorCode::new(Color::DarkGray, Color::Black)),
+                1 => (LEFT_HALF_BLOCK, level_color(cell as f32 / VU_WIDTH as f32)),
+                _ => (FULL_BLOCK, level_color(cell as f32 / VU_WIDTH as f32)),
+            };
+            screen.text_char(c, left + 2 + cell, row, color);
+        }
+    }
+}
+
+impl SpaceFox {
+    pub fn new(rhythm: Option<Rhythm>) -> Self {
         let mut world = [Default::default(); 30];
 
+        // The ship's wings are open at the front, so we see both sides of them
         world[PLAYER] = Model {
             prims: SHIP_TRIS,
-            pos: v([0.0, -3.0, 15.0]),
-            scale: 1.0,
+            pos: SHIP_START,
+            hidden_lines: true,
+            ..Default::default()
         };
 
         world[BLOCK] = Model {
             prims: BLOCK_QUADS,
-            pos: v([-3.0, -3.0, 200.0]),
-            scale: 2.0,
+            pos: v([-3.0, -3.0, HORIZON]),
+            // Twice as wide and tall, but the same depth
+            scale: v([2.0, 2.0, 1.0]),
+            cull_back_faces: true,
+            hidden_lines: true,
+            ..Default::default()
         };
 
         Self {
-            lines: [[Default::default(); 100], [Default::default(); 100]],
-            end: [0, 0],
-            b: 0,
+            raster: Raster::new(),
+            solid: false,
             world,
             xvel: 0.0,
             yvel: 0.0,
             score: 0,
+            tower_motion: Vec3f::default(),
+            tower_whoosh: None,
+            closest_pass: f32::MAX,
+            near_miss_boost: 0,
+            paused: false,
+            rhythm,
+            camera: Camera {
+                pos: Vec3f::default(),
+                orientation: Quat::IDENTITY,
+                fov: DEFAULT_FOV,
+            },
+            roll: 0.0,
+            pitch: 0.0,
         }
     }
 
-    fn swap_buffer(&mut self) {
-        self.b ^= 1
+    fn tower_offset(&self) -> Vec3f {
+        self.world[BLOCK].pos - self.world[PLAYER].pos
     }
 
-    pub fn update(&mut self, rand: u8) -> bool {
-        {
+    // Where the tower sounds like it is from the ship
+    fn tower_emitter(&self) -> Emitter {
+        let offset = self.tower_offset();
+        let motion = self.tower_motion;
+        Emitter {
+            offset: [offset.x, offset.y, offset.z],
+            velocity: [
+                motion.x * TICKS_PER_SECOND,
+                motion.y * TICKS_PER_SECOND,
+                motion.z * TICKS_PER_SECOND,
+            ],
+        }
+    }
+
+    // music_position is how far into GAME_MUSIC the player is hearing,
+    // which only matters in rhythm mode
+    pub fn update(&mut self, rand: u8, music_position: Option<usize>) -> bool {
+        let tower_before = self.tower_offset();
+        if let Some(rhythm) = &mut self.rhythm {
+            // Placed from where the music is rather than moved each update,
+            // so it stays in time even if updates don't
+            let ship_z = self.world[PLAYER].pos.z;
+            match music_position.and_then(|position| rhythm.tower(position)) {
+                Some((seconds, lane)) => {
+                    let z = ship_z + seconds * RHYTHM_TOWER_SPEED;
+                    if z < -5.0 {
+                        rhythm.next_tower();
+                        self.world[BLOCK].pos.z = HORIZON;
+                        self.tower_passed();
+                    } else {
+                        self.world[BLOCK].pos.z = z.min(HORIZON);
+                        self.world[BLOCK].pos.x = lane as f32 * RHYTHM_LANE_WIDTH;
+                    }
+                }
+                // Waits on the horizon until the music comes back
+                None => self.world[BLOCK].pos.z = HORIZON,
+            }
+        } else {
             let z = self.world[BLOCK].pos.z;
             self.world[BLOCK].pos.z -= (0.01 * z + 3.0).max(1.0);
             if self.world[BLOCK].pos.z < -5.0 {
-                self.world[BLOCK].pos.z = 200.0;
+                self.world[BLOCK].pos.z = HORIZON;
                 let interp = ((rand as f32) / 255.0) * 6.0 - 3.0;
                 self.world[BLOCK].pos.x = interp;
-
-                self.score += 1;
+                self.tower_passed();
             }
         }
+        self.near_miss_boost = self.near_miss_boost.saturating_sub(1);
 
         {
             let x = &mut self.world[PLAYER].pos.x;
@@ -334,129 +838,139 @@ impl SpaceFox {
             }
         }
 
-        let mut next_line = 0;
-        for Model { prims, pos, scale } in self.world {
-            const ASPECT_X: f32 = BUFFER_WIDTH as f32;
-            const ASPECT_Y: f32 = -(BUFFER_HEIGHT as f32);
-            const XOFF: f32 = BUFFER_WIDTH as f32 / 2.0;
-            const YOFF: f32 = BUFFER_HEIGHT as f32 / 2.0;
+        // Bank and pitch into the way we're going, like in Star Fox,
+        // with the nose swinging round a little as it banks
+        let turn = self.xvel / XSPEED;
+        let climb = self.yvel / XSPEED;
+        self.roll += (-turn * MAX_ROLL - self.roll) * LEAN_EASE;
+        self.pitch += (-climb * MAX_PITCH - self.pitch) * LEAN_EASE;
+        self.world[PLAYER].rotation =
+            Quat::from_euler(-self.roll * YAW_PER_ROLL, self.pitch, self.roll);
 
-            let modl = |p: &Vec3f| -> Vec3f {
-                Vec3f {
-                    x: p.x + pos.x,
-                    y: p.y + pos.y,
-                    z: p.z + pos.z,
-                }
-            };
-            let persp = |p: Vec3f| -> (f32, f32, f32) {
-                if p.z.abs() < 0.01 {
-                    (0.0, 0.0, 0.0)
-                } else {
-                    (
-                        p.x * scale / p.z * ASPECT_X + XOFF,
-                        p.y * scale / p.z * ASPECT_Y + YOFF,
-                        p.z,
-                    )
-                }
-            };
-
-            let i = |p: &Vec3f| -> (i8, i8, i8) {
-                let (x, y, z) = persp(modl(p));
-                (x as i8, y as i8, z as i8)
-            };
+        // Stay behind the ship without ever quite catching up with it
+        let ship = self.world[PLAYER].pos;
+        self.camera.pos = (ship - SHIP_START) * CAMERA_FOLLOW;
+        self.camera.orientation = Quat::from_euler(0.0, 0.0, self.roll * CAMERA_ROLL);
 
-            for p in prims {
-                match p {
-                    Prim::Noop => {}
-                    Prim::Tri(p1, p2, p3) => {
-                        if next_line + 3 >= self.lines[0].len() {
-                            break;
-                        }
-
-                        let (x1, y1, z1) = i(p1);
-                        let (x2, y2, z2) = i(p2);
-                        let (x3, y3, z3) = i(p3);
-
-                        if x1 < 0 || x1 > BUFFER_WIDTH as i8 {
-                            continue;
-                        }
-                        if x2 < 0 || x2 > BUFFER_WIDTH as i8 {
-                            continue;
-                        }
-                        if x2 < 0 || x2 > BUFFER_WIDTH as i8 {
-                            continue;
-                        }
-
-                        self.lines[self.b][next_line + 0] = [x1, y1, z1, x2, y2, z2, 0];
-                        self.lines[self.b][next_line + 1] = [x2, y2, z2, x3, y3, z3, 0];
-                        self.lines[self.b][next_line + 2] = [x3, y3, z3, x1, y1, z1, 0];
-
-                        next_line += 3;
-                    }
-                    Prim::Quad(p1, p2, p3, p4) => {
-                        if next_line + 4 >= self.lines[0].len() {
-                            break;
-                        }
-
-                        let (x1, y1, z1) = i(p1);
-                        let (x2, y2, z2) = i(p2);
-                        let (x3, y3, z3) = i(p3);
-                        let (x4, y4, z4) = i(p4);
-
-                        if x1 < 0 || x1 > BUFFER_WIDTH as i8 {
-                            continue;
-                        }
-                        if x2 < 0 || x2 > BUFFER_WIDTH as i8 {
-                            continue;
-                        }
-                        if x2 < 0 || x2 > BUFFER_WIDTH as i8 {
-                            continue;
-                        }
-                        if x4 < 0 || x4 > BUFFER_WIDTH as i8 {
-                            continue;
-                        }
-
-                        self.lines[self.b][next_line + 0] = [x1, y1, z1, x2, y2, z2, 1];
-                        self.lines[self.b][next_line + 1] = [x2, y2, z2, x3, y3, z3, 1];
-                        self.lines[self.b][next_line + 2] = [x3, y3, z3, x4, y4, z4, 1];
-                        self.lines[self.b][next_line + 3] = [x4, y4, z4, x1, y1, z1, 1];
-
-                        next_line += 4;
-                    }
-                }
-            }
+        // When a new tower appears, keep the old motion rather than
+        // treating the jump back to the horizon as movement
+        let tower_after = self.tower_offset();
+        if tower_after.z <= tower_before.z {
+            self.tower_motion = tower_after - tower_before;
         }
 
-        self.end[self.b] = next_line;
-
         let p1 = self.world[BLOCK].pos;
         let p2 = self.world[PLAYER].pos;
         let dx = p1.x - p2.x;
         // let dy = p1.y - p2.y;
         let dz = p1.z - p2.z;
 
-        dx * dx + dz * dz > 1.0
+        let distance_squared = dx * dx + dz * dz;
+        self.closest_pass = self.closest_pass.min(distance_squared);
+        distance_squared > 1.0
+    }
+
+    fn tower_passed(&mut self) {
+        self.score += 1;
+
+        if self.closest_pass < NEAR_MISS_DISTANCE * NEAR_MISS_DISTANCE {
+            self.near_miss_boost = NEAR_MISS_BOOST;
+        }
+        self.closest_pass = f32::MAX;
+    }
+
+    // How hectic things are, from 0 to 255, for the adaptive music.
+    // It builds up with the score, and gets a kick from dodging around
+    // and from scraping past towers.
+    pub fn intensity(&self) -> u8 {
+        let from_score = (self.score * 12).min(160);
+        let dodging = if self.xvel != 0.0 || self.yvel != 0.0 {
+            32
+        } else {
+            0
+        };
+        (from_score + dodging + self.near_miss_boost as u64).min(255) as u8
     }
 
-    pub fn draw(&mut self) {
-        let d = self.b ^ 1;
-        clear_lines(&self.lines[d], self.end[d]);
-        plot_line(10, 24, 40, 10, '/');
-        plot_line(70, 24, 40, 10, '\\');
-        plot_num_right_justified(
-            4,
-            self.score as isize,
-            BUFFER_WIDTH - 4 - 1,
-            1,
-            ColorCode::new(Color::Yellow, Color::Black),
-        );
-        self.end[d] = 0;
-        draw_lines(&self.lines[self.b], self.end[self.b]);
-        self.swap_buffer();
+    // How fast the music plays, 1.0 at the start and faster as you go
+    pub fn music_rate(&self) -> f32 {
+        (1.0 + self.score as f32 * MUSIC_RATE_PER_POINT).min(MAX_MUSIC_RATE)
+    }
+
+    pub fn draw(&mut self, screen: &mut dyn Renderer) {
+        let dimensions = screen.dimensions();
+        let (width, height) = (dimensions.width, dimensions.height);
+
+        let horizon = ColorCode::new(Color::LightGray, Color::Black);
+        for x in 0..width {
+            let c = GRAD_HOR[x * GRAD_HOR.len() / width];
+            screen.plot(x, height / 2, (c as char, horizon));
+        }
+        // The runway, from the bottom corners to where it meets the horizon
+        let runway = ColorCode::new(Color::DarkGray, Color::Black);
+        let vanishing_point = (width / 2, height * 2 / 5);
+        screen.line((width / 8, height - 1), vanishing_point, runway);
+        screen.line((width * 7 / 8, height - 1), vanishing_point, runway);
+
+        // Every face has to be in the depth buffer before any lines are drawn,
+        // so lines can be hidden by models further along the world
+        let camera = self.camera;
+        self.raster.clear();
+        for_each_face(&self.world, &camera, |index, _, corners, cbit| {
+            // In wireframe this only keeps the depth, for hiding lines
+            let colors = self.solid.then_some(if cbit == 0 {
+                (Color::Cyan, Color::LightCyan)
+            } else {
+                (Color::Red, Color::LightRed)
+            });
+            fill_prim(&mut self.raster, &camera, corners, index as u8, colors);
+        });
+        draw_raster(screen, &self.raster);
+
+        if !self.solid {
+            for_each_face(&self.world, &camera, |index, model, corners, cbit| {
+                let owner = if model.hidden_lines { index as i16 } else { -1 };
+                // Every edge is clipped on its own, so a shape that's
+                // partly off screen still draws whatever is on screen
+                for edge in 0..corners.len() {
+                    let from = corners[edge];
+                    let to = corners[(edge + 1) % corners.len()];
+                    if let Some(line) = edge_to_line(&camera, from, to, cbit, owner, width, height)
+                    {
+                        plot_line_depth(screen, &line, &self.raster);
+                    }
+                }
+            });
+        }
+
+        let yellow = ColorCode::new(Color::Yellow, Color::Black);
+        let columns = dimensions.columns;
+        // Towers passed
+        screen.text_char(glyphs::TOWER, columns - 4 - 2, 1, yellow);
+        screen.text_num_right_justified(4, self.score as isize, columns - 4 - 1, 1, yellow);
+        if self.paused {
+            let col = screen.text_char(glyphs::PAUSE, 1, 1, yellow);
+            screen.text(PAUSED_LABEL, col + 1, 1, yellow);
+        }
     }
 
     pub fn key(&mut self, k: DecodedKey) {
-        const XSPEED: f32 = 0.7;
+        if k == DecodedKey::Unicode('p') {
+            self.paused = !self.paused;
+            // The music kept going while we were paused,
+            // so the tower that was coming is long gone
+            if let Some(rhythm) = &mut self.rhythm {
+                rhythm.next_tower();
+            }
+            return;
+        }
+        if k == DecodedKey::Unicode('f') {
+            self.solid = !self.solid;
+            return;
+        }
+        if self.paused {
+            return;
+        }
         match k {
             DecodedKey::Unicode('a') => self.xvel = -XSPEED,
             DecodedKey::Unicode('d') => self.xvel = XSPEED,
@@ -471,97 +985,220 @@ impl SpaceFox {
     }
 }
 
-fn clear_lines(lb: &LineBank, end: usize) {
-    for l in &lb[0..end] {
-        if l != &[0, 0, 0, 0, 0, 0, 0] {
-            clear_line(l);
+const PAUSED_LABEL: &str = "PAUSED";
+
+// Calls f with every face in the world that we might see, with its corners
+// in camera space, and 1 for a tower or 0 for the ship like Line
+fn for_each_face(world: &World, camera: &Camera, mut f: impl FnMut(usize, &Model, &[Vec3f], i16)) {
+    let view = camera.view();
+    for (index, model) in world.iter().enumerate() {
+        if model.prims.is_empty() {
+            continue;
         }
-    }
-}
+        let to_camera = view * model.transform();
 
-fn draw_lines(lb: &LineBank, end: usize) {
-    for l in &lb[0..end] {
-        if l != &[0, 0, 0, 0, 0, 0, 0] {
-            plot_line_depth(l);
+        for p in model.prims {
+            // Red for the towers, cyan for the ship
+            let (corners, count, cbit) = match *p {
+                Prim::Noop => continue,
+                Prim::Tri(p1, p2, p3) => ([p1, p2, p3, p3], 3, 0),
+                Prim::Quad(p1, p2, p3, p4) => ([p1, p2, p3, p4], 4, 1),
+            };
+            let corners = corners.map(|c| to_camera.transform_point(c));
+            let corners = &corners[..count];
+            if model.cull_back_faces && !faces_camera(corners) {
+                continue;
+            }
+            f(index, model, corners, cbit);
         }
     }
 }
 
-// Bresenham's line algorithm, adapted from:
-// https://en.wikipedia.org/wiki/Bresenham%27s_line_algorithm
+// Takes an edge in camera space to a line on a screen width by height,
+// or None if none of it shows
+fn edge_to_line(
+    camera: &Camera,
+    from: Vec3f,
+    to: Vec3f,
+    cbit: i16,
+    owner: i16,
+    width: usize,
+    height: usize,
+) -> Option<Line> {
+    let max_x = width as f32 - 1.0;
+    let max_y = height as f32 - 1.0;
 
-pub fn plot_line_depth(&[x1, y1, z1, x2, y2, z2, cbit]: &Line) {
-    let color = if cbit == 0 {
-        Color::LightCyan
-    } else {
-        Color::Red
-    };
-    let mut x1 = x1 as i32;
-    let mut y1 = y1 as i32;
-    let x2 = x2 as i32;
-    let y2 = y2 as i32;
+    let (from, to) = clip::clip_near(from, to)?;
+    let (from, to) = clip::clip_screen(
+        camera.project(from, width, height),
+        camera.project(to, width, height),
+        max_x,
+        max_y,
+    )?;
+    // Everything is on screen now, so nothing is negative and adding a half rounds
+    let round = |x: f32| (x + 0.5) as i16;
+    let subcells = |x: f32| round(x * LINE_SUBCELLS);
+    Some([
+        subcells(from.x),
+        subcells(from.y),
+        round(from.z),
+        subcells(to.x),
+        subcells(to.y),
+        round(to.z),
+        cbit,
+        owner,
+    ])
+}
 
-    let dx = i32::abs(x2 - x1);
-    let dy = -i32::abs(y2 - y1);
-    let sx = if x1 < x2 { 1 } else { -1 };
-    let sy = if y1 < y2 { 1 } else { -1 };
-    let mut error = dx + dy;
+// Where the light comes from in camera space, above and to the left of us
+const LIGHT: Vec3f = v([-0.4, 0.8, -0.45]);
+// How lit a surface facing away from the light still is
+const AMBIENT: f32 = 0.2;
 
-    for _ in 0..24 {
-        let percentage = i32::abs(x2 - x1) as f32 / (dx as f32 + 1.0);
-        let depth = (z1 as f32 * percentage) + (z2 as f32 * (1.0 - percentage));
-        let i = (depth) / 60.0;
-        let i = (i * GRAD.len() as f32)
-            .max(0.0)
-            .min(GRAD.len() as f32 - 1.0);
-
-        // const GRAD: &[char] = &['1', '2', '3', '4', '5', '6', '7', '8', '9'];
-        const GRAD: &[char] = &['#', '#', '#', '@', '*', ',', ',', '.'];
-        let c = if cbit == 1 { GRAD[i as usize] } else { '*' };
-
-        if x1 >= 0 && x1 < BUFFER_WIDTH as i32 && y1 >= 0 && y1 < BUFFER_HEIGHT as i32 {
-            plot(
-                c,
-                x1 as usize,
-                y1 as usize,
-                ColorCode::new(color, Color::Black),
-            );
-        }
-        if x1 == x2 && y1 == y2 {
-            break;
-        }
-        let e2 = 2 * error;
-        if e2 >= dy {
-            if x1 == x2 {
-                break;
+// Whether we're looking at the front of a face, from its corners in camera space
+fn faces_camera(corners: &[Vec3f]) -> bool {
+    let inwards = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+    // The camera is at the origin, so the face points at it if
+    // its inside is further along the way we're looking at it
+    inwards.dot(corners[0]) > 0.0
+}
+
+// Fills in a prim's corners (in camera space) with flat shading,
+// which is Lambert's cosine law once per triangle,
+// see https://en.wikipedia.org/wiki/Lambertian_reflectance
+// Without colors it's only filled into the depth buffer.
+fn fill_prim(
+    raster: &mut Raster,
+    camera: &Camera,
+    corners: &[Vec3f],
+    owner: u8,
+    colors: Option<(Color, Color)>,
+) {
+    let light = LIGHT.normalized();
+    // Quads are two triangles sharing the first corner
+    for i in 1..corners.len() - 1 {
+        let triangle = [corners[0], corners[i], corners[i + 1]];
+        let look = colors.map(|(dark, bright)| {
+            let mut normal = (triangle[1] - triangle[0])
+                .cross(triangle[2] - triangle[0])
+                .normalized();
+            // Models that aren't culled show both sides, so light whichever side we see
+            if normal.dot(triangle[0]) > 0.0 {
+                normal = -normal;
             }
-            error += dy;
-            x1 += sx;
+            let lambert = normal.dot(light).max(0.0);
+            raster::shade(AMBIENT + (1.0 - AMBIENT) * lambert, dark, bright)
+        });
+
+        let (clipped, count) = clip::clip_triangle_near(triangle);
+        let on_screen = clipped.map(|c| camera.project(c, raster::WIDTH, raster::HEIGHT));
+        for j in 1..count.saturating_sub(1) {
+            raster.fill_triangle(on_screen[0], on_screen[j], on_screen[j + 1], owner, look);
         }
-        if e2 <= dx {
-            if y1 == y2 {
-                break;
+    }
+}
+
+// The raster has its own grid, so each of its cells
+// covers however much of the screen that works out to.
+// Cells on the edge of a shape only get the quarters it covers,
+// which in text mode takes one of our quarter block glyphs.
+fn draw_raster(screen: &mut dyn Renderer, raster: &Raster) {
+    let Dimensions { width, height, .. } = screen.dimensions();
+    for (col, row, c, color, quarters) in raster.cells() {
+        let x = col * width / raster::WIDTH;
+        let y = row * height / raster::HEIGHT;
+        let next_x = (col + 1) * width / raster::WIDTH;
+        let next_y = (row + 1) * height / raster::HEIGHT;
+        if quarters == glyphs::WHOLE_CELL {
+            screen.fill(x, y, next_x - x, next_y - y, (c, color));
+        } else if next_x - x == 1 && next_y - y == 1 {
+            // A quarter block can't be shaded, so it's whichever colour
+            // the shade shows the most of
+            let main = if render::coverage(c) >= 2 || color.background() == Color::Black {
+                color.foreground()
+            } else {
+                color.background()
+            };
+            let c = glyphs::quadrants(quarters);
+            screen.plot(x, y, (c, ColorCode::new(main, Color::Black)));
+        } else {
+            let mid_x = (x + next_x) / 2;
+            let mid_y = (y + next_y) / 2;
+            for (bit, left, top, right, bottom) in [
+                (glyphs::TOP_LEFT, x, y, mid_x, mid_y),
+                (glyphs::TOP_RIGHT, mid_x, y, next_x, mid_y),
+                (glyphs::BOTTOM_LEFT, x, mid_y, mid_x, next_y),
+                (glyphs::BOTTOM_RIGHT, mid_x, mid_y, next_x, next_y),
+            ] {
+                if quarters & bit != 0 {
+                    screen.fill(left, top, right - left, bottom - top, (c, color));
+                }
             }
-            error += dx;
-            y1 += sy;
         }
     }
 }
 
-pub fn plot_line(mut x1: i32, mut y1: i32, x2: i32, y2: i32, c: char) {
+// Bresenham's line algorithm, adapted from:
+// https://en.wikipedia.org/wiki/Bresenham%27s_line_algorithm
+
+// Anything in raster that's in front of the line hides it, see Line.
+// Each cell gets the character that fits where the line crosses it, see render::line_glyph.
+fn plot_line_depth(
+    screen: &mut dyn Renderer,
+    &[x1, y1, z1, x2, y2, z2, cbit, owner]: &Line,
+    raster: &Raster,
+) {
+    let Dimensions { width, height, .. } = screen.dimensions();
+    let hidden = |x: usize, y: usize, depth: f32| {
+        let col = x * raster::WIDTH / width;
+        let row = y * raster::HEIGHT / height;
+        owner >= 0 && raster.hides(col, row, depth, owner as u8)
+    };
+    let ends = (
+        (x1 as f32 / LINE_SUBCELLS, y1 as f32 / LINE_SUBCELLS),
+        (x2 as f32 / LINE_SUBCELLS, y2 as f32 / LINE_SUBCELLS),
+    );
+    // The cells the ends are in
+    let cell = |x: i16| (x as f32 / LINE_SUBCELLS + 0.5) as i32;
+    let mut x1 = cell(x1);
+    let mut y1 = cell(y1);
+    let x2 = cell(x2);
+    let y2 = cell(y2);
+
     let dx = i32::abs(x2 - x1);
     let dy = -i32::abs(y2 - y1);
     let sx = if x1 < x2 { 1 } else { -1 };
     let sy = if y1 < y2 { 1 } else { -1 };
     let mut error = dx + dy;
 
-    for _ in 0..24 {
-        if x1 >= 0 && x1 < BUFFER_WIDTH as i32 && y1 >= 0 && y1 < BUFFER_HEIGHT as i32 {
-            plot(
-                c,
+    // How far along the line we are, for the depth
+    let steps = dx.max(-dy).max(1) as f32;
+    let mut step = 0;
+
+    loop {
+        let along = step as f32 / steps;
+        let depth = z1 as f32 * (1.0 - along) + z2 as f32 * along;
+        step += 1;
+
+        // The characters show which way the line goes, so the colour
+        // shows how close a tower is
+        let color = match cbit {
+            0 => Color::LightCyan,
+            _ if depth < NEAR_TOWER_DEPTH => Color::LightRed,
+            _ => Color::Red,
+        };
+
+        if x1 >= 0
+            && (x1 as usize) < width
+            && y1 >= 0
+            && (y1 as usize) < height
+            && !hidden(x1 as usize, y1 as usize, depth)
+        {
+            let c = render::line_glyph(ends.0, ends.1, x1 as usize, y1 as usize);
+            screen.plot(
                 x1 as usize,
                 y1 as usize,
-                ColorCode::new(Color::DarkGray, Color::Black),
+                (c, ColorCode::new(color, Color::Black)),
             );
         }
         if x1 == x2 && y1 == y2 {
@@ -584,53 +1221,3 @@ pub fn plot_line(mut x1: i32, mut y1: i32, x2: i32, y2: i32, c: char) {
         }
     }
 }
-
-pub fn clear_line(&[x1, y1, _, x2, y2, _, _]: &Line) {
-    let mut x1 = x1 as i32;
-    let mut y1 = y1 as i32;
-    let x2 = x2 as i32;
-    let y2 = y2 as i32;
-
-    let dx = i32::abs(x2 - x1);
-    let dy = -i32::abs(y2 - y1);
-    let sx = if x1 < x2 { 1 } else { -1 };
-    let sy = if y1 < y2 { 1 } else { -1 };
-    let mut error = dx + dy;
-
-    for _ in 0..24 {
-        if x1 >= 0 && x1 < BUFFER_WIDTH as i32 && y1 >= 0 && y1 < BUFFER_HEIGHT as i32 {
-            if y1 == 12 {
-                let c = GRAD_HOR[x1 as usize / 8];
-                plot(
-                    c as char,
-                    x1 as usize,
-                    y1 as usize,
-                    ColorCode::new(Color::LightGray, Color::Black),
-                );
-            } else {
-                plot(
-                    ' ',
-                    x1 as usize,
-                    y1 as usize,
-                    ColorCode::new(Color::Black, Color::Black),
-                );
-            }
-        }
-
-        let e2 = 2 * error;
-        if e2 >= dy {
-            if x1 == x2 {
-                break;
-            }
-            error += dy;
-            x1 += sx;
-        }
-        if e2 <= dx {
-            if y1 == y2 {
-                break;
-            }
-            error += dx;
-            y1 += sy;
-        }
-    }
-}
@@ -1,35 +1,96 @@
-use core::slice::from_raw_parts;
+use super::rhythm::{Beat, BeatMap};
+use crate::{
+    audio::{layers::Layer, playlist::Cue, Ending},
+    pc_speaker::melody::*,
+};
 
-use lazy_static::lazy_static;
+// Either a headerless 16 bit stereo .raw at 48kHz, any PCM or IMA ADPCM .wav,
+// or a .mod, see audio::Track::from_bytes. We decode the bytes ourselves,
+// so they don't need to be aligned for i16 anymore.
+// build.rs encodes every .raw and .wav in music/ as ADPCM into OUT_DIR,
+// which is a quarter the size, so that's what we ship.
+// pub static MUSIC: &[u8] = include_bytes!("../../../../../../Documents/snippet.raw");
+// pub static MUSIC: &[u8] = include_bytes!("../../music/something_like_megaman2.raw");
+// It also splits each one at 2kHz, and we ship the halves instead of the whole song:
+// the lows as the tune, and the highs (mostly drums) as a layer on top.
+// Both halves are as long as the song, so this takes twice the room
+// the mixdown did. A song exported with its own stems could ship
+// something much smaller, like a drum loop, instead.
+pub static MUSIC: &[u8] = include_bytes!(concat!(
+    env!("OUT_DIR"),
+    "/something_like_megaman2.lows.adpcm.wav"
+));
+// The drums come in once SpaceFox::intensity says things are getting going,
+// which is a few towers in, or sooner if you're dodging about
+static MUSIC_LAYERS: [Layer; 1] = [Layer {
+    bytes: include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/something_like_megaman2.highs.adpcm.wav"
+    )),
+    fades_in_at: 64,
+}];
 
-// https://users.rust-lang.org/t/can-i-conveniently-compile-bytes-into-a-rust-program-with-a-specific-alignment/24049/2
-// I found this really cool trick for aligning include_bytes correctly!
-// I would have been totally lost without this, many thanks to ExpHP!!!
+// Which music goes with which screen, see Game::cue_music.
+// We only have the one song so far, so the menu and the game share it,
+// and it carries on from the menu into the game without restarting.
+// The menu has the layers too only so Cue::same_as sees the same cue and
+// playback carries on. Game::cue_music keeps the menu at intensity 0,
+// so they stay silent there.
+// To give a song an intro, loop it from the frame the intro ends on.
+pub static MENU_MUSIC: Cue = Cue {
+    bytes: MUSIC,
+    ending: Ending::LoopFrom(0),
+    layers: &MUSIC_LAYERS,
+};
+pub static GAME_MUSIC: Cue = Cue {
+    bytes: MUSIC,
+    ending: Ending::LoopFrom(0),
+    layers: &MUSIC_LAYERS,
+};
+// Where the towers go in rhythm mode, found by build.rs
+pub static GAME_BEATS: BeatMap = include!(concat!(
+    env!("OUT_DIR"),
+    "/something_like_megaman2.beats.rs"
+));
+// A few descending notes for when you crash
+pub static GAME_OVER_STINGER: Cue = Cue {
+    bytes: include_bytes!("../../music/game_over.mod"),
+    ending: Ending::Stop,
+    layers: &[],
+};
 
-lazy_static! {
-    pub static ref WAV_DATA_SAMPLES: &'static [i16] = {
-        // This struct is generic in Bytes to admit unsizing coercions.
-        #[repr(C)] // guarantee 'bytes' comes after '_align'
-        struct AlignedTo<Align, Bytes: ?Sized> {
-            _align: [Align; 0],
-            bytes: Bytes,
-        }
-
-        // dummy static used to create aligned data
-        static ALIGNED: &'static AlignedTo<i16, [u8]> = &AlignedTo {
-            _align: [],
-            // bytes: *include_bytes!("../../../../../../Documents/snippet.raw"),
-            // bytes: *include_bytes!("../../../../../../Documents/something_like_megaman2.raw"),
-            bytes: *include_bytes!("../../music/something_like_megaman2.raw"),
-        };
-
-        static ALIGNED_BYTES: &'static [u8] = &ALIGNED.bytes;
-
-        unsafe {
-            from_raw_parts(
-                ALIGNED_BYTES.as_ptr() as *const i16,
-                ALIGNED_BYTES.len() / 2,
-            )
-        }
-    };
-}
+// For machines without a sound card, a square wave arrangement of
+// the main riff that the PC speaker can manage. Durations are in timer ticks
+// (~55ms each), so a 3 tick note is roughly an eighth note at 180 bpm.
+pub const SPEAKER_THEME: &[Note] = &[
+    n(E4, 3),
+    n(E4, 3),
+    n(B4, 3),
+    n(E4, 3),
+    n(D5, 3),
+    n(E4, 3),
+    n(C5, 3),
+    n(B4, 3),
+    n(E4, 3),
+    n(E4, 3),
+    n(B4, 3),
+    n(E4, 3),
+    n(G4, 6),
+    n(A4, 6),
+    n(C4, 3),
+    n(C4, 3),
+    n(G4, 3),
+    n(C4, 3),
+    n(B4, 3),
+    n(C4, 3),
+    n(A4, 3),
+    n(G4, 3),
+    n(D4, 3),
+    n(D4, 3),
+    n(A4, 3),
+    n(D4, 3),
+    n(F4, 3),
+    n(E4, 3),
+    n(D4, 6),
+    n(REST, 6),
+];
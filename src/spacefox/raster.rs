@@ -0,0 +1,149 @@
+use pluggable_interrupt_os::vga_buffer::{Color, ColorCode, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+use super::{
+    glyphs::{BOTTOM_LEFT, BOTTOM_RIGHT, TOP_LEFT, TOP_RIGHT},
+    math::Vec3f,
+    render::{DARK_SHADE, FULL_BLOCK, LIGHT_SHADE, MEDIUM_SHADE},
+};
+
+// Fills triangles into a grid of character cells, keeping whatever is nearest
+// in each cell. How lit a triangle is picks one of code page 437's shade
+// characters and a colour for it. Each quarter of a cell is sampled,
+// so the edges of shapes can be drawn with quarter blocks, see draw_raster.
+// See https://www.scratchapixel.com/lessons/3d-basic-rendering/rasterization-practical-implementation
+
+// The raster's own grid, the same as text mode. Renderers with more room
+// than that scale each cell up, see draw_raster.
+pub const WIDTH: usize = BUFFER_WIDTH;
+pub const HEIGHT: usize = BUFFER_HEIGHT;
+const CELLS: usize = WIDTH * HEIGHT;
+
+// Where each quarter of a cell is sampled, from its top left
+const QUARTERS: [(u8, f32, f32); 4] = [
+    (TOP_LEFT, 0.25, 0.25),
+    (TOP_RIGHT, 0.75, 0.25),
+    (BOTTOM_LEFT, 0.25, 0.75),
+    (BOTTOM_RIGHT, 0.75, 0.75),
+];
+
+pub struct Raster {
+    // One over the depth of what's in each cell, so bigger is nearer,
+    // and 0 is nothing at all
+    inverse_depth: [f32; CELLS],
+    // Which model that nearest thing belongs to
+    owners: [u8; CELLS],
+    cells: [Option<(char, ColorCode)>; CELLS],
+    // Which quarters of each cell anything covers, see glyphs::quadrants
+    quarters: [u8; CELLS],
+}
+
+impl Raster {
+    pub const fn new() -> Self {
+        Self {
+            inverse_depth: [0.0; CELLS],
+            owners: [0; CELLS],
+            cells: [None; CELLS],
+            quarters: [0; CELLS],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.inverse_depth = [0.0; CELLS];
+        self.owners = [0; CELLS];
+        self.cells = [None; CELLS];
+        self.quarters = [0; CELLS];
+    }
+
+    // Whether something from another model is in front of depth in this cell.
+    // A model's own faces don't count, as its edges are on them.
+    pub fn hides(&self, col: usize, row: usize, depth: f32, owner: u8) -> bool {
+        let i = row * WIDTH + col;
+        self.owners[i] != owner && self.inverse_depth[i] * depth > 1.0
+    }
+
+    // Every cell something was drawn in, as column, row, character, colour,
+    // and which quarters of it are covered
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize, char, ColorCode, u8)> + '_ {
+        self.cells.iter().enumerate().filter_map(|(i, cell)| {
+            cell.map(|(c, color)| (i % WIDTH, i / WIDTH, c, color, self.quarters[i]))
+        })
+    }
+
+    // a, b, and c are a column and row on screen, with the depth in z,
+    // which has to be in front of the camera. Either winding is fine.
+    // owner is which model it's from, and without a look it only
+    // goes into the depth buffer, for hiding lines behind it.
+    pub fn fill_triangle(
+        &mut self,
+        a: Vec3f,
+        b: Vec3f,
+        c: Vec3f,
+        owner: u8,
+        look: Option<(char, ColorCode)>,
+    ) {
+        // Twice the triangle's area, negative if it winds the other way
+        let edge = |from: Vec3f, to: Vec3f, x: f32, y: f32| {
+            (to.x - from.x) * (y - from.y) - (to.y - from.y) * (x - from.x)
+        };
+        let area = edge(a, b, c.x, c.y);
+        if area == 0.0 {
+            return;
+        }
+
+        let left = a.x.min(b.x).min(c.x).max(0.0) as usize;
+        let right = a.x.max(b.x).max(c.x).min(WIDTH as f32 - 1.0);
+        let top = a.y.min(b.y).min(c.y).max(0.0) as usize;
+        let bottom = a.y.max(b.y).max(c.y).min(HEIGHT as f32 - 1.0);
+        if right < 0.0 || bottom < 0.0 {
+            return;
+        }
+
+        for row in top..=bottom as usize {
+            for col in left..=right as usize {
+                // The cell's depth is taken from the nearest quarter it covers
+                let mut quarters = 0;
+                let mut inverse_depth = 0.0f32;
+                for (bit, dx, dy) in QUARTERS {
+                    let x = col as f32 + dx;
+                    let y = row as f32 + dy;
+                    // How much of each corner is in this point, which are
+                    // all positive inside the triangle
+                    let wa = edge(b, c, x, y) / area;
+                    let wb = edge(c, a, x, y) / area;
+                    let wc = edge(a, b, x, y) / area;
+                    if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                        continue;
+                    }
+                    quarters |= bit;
+                    // One over depth is what's linear across the screen
+                    inverse_depth = inverse_depth.max(wa / a.z + wb / b.z + wc / c.z);
+                }
+                if quarters == 0 {
+                    continue;
+                }
+
+                let i = row * WIDTH + col;
+                // The nearest thing is drawn over everything this cell covers
+                self.quarters[i] |= quarters;
+                if inverse_depth > self.inverse_depth[i] {
+                    self.inverse_depth[i] = inverse_depth;
+                    self.owners[i] = owner;
+                    self.cells[i] = look;
+                }
+            }
+        }
+    }
+}
+
+// How a surface looks when it's this lit, from 0 (dark) to 1 (facing the light),
+// in eight steps: the shades in the dark colour over black, then the shades
+// in the bright colour over the dark one, and a solid bright block at the top
+pub fn shade(brightness: f32, dark: Color, bright: Color) -> (char, ColorCode) {
+    const SHADES: [char; 4] = [LIGHT_SHADE, MEDIUM_SHADE, DARK_SHADE, FULL_BLOCK];
+    let step = ((brightness * 8.0) as usize).min(7);
+    match step {
+        0..=3 => (SHADES[step], ColorCode::new(dark, Color::Black)),
+        4..=6 => (SHADES[step - 4], ColorCode::new(bright, dark)),
+        _ => (FULL_BLOCK, ColorCode::new(bright, Color::Black)),
+    }
+}
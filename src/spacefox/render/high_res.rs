@@ -0,0 +1,165 @@
+use pluggable_interrupt_os::vga_buffer::{Color, ColorCode};
+use volatile::Volatile;
+
+use super::{coverage, mode_13h::TEXT_COLORS, Cell, Dimensions, Renderer};
+use crate::{
+    isa::vga::{Mode, Vga, FONT_BYTES, GLYPH_HEIGHT, GLYPH_STRIDE},
+    pci::bochs_display::{BochsDisplay, Pixel},
+    phys_alloc::PhysAllocator,
+};
+
+// 640x480 in true colour on the Bochs display adapter, with two pages of video
+// memory: one showing while the other is drawn, then they swap, so nothing
+// gets copied. Text uses the text mode font at full size, which fits 80x30.
+
+const WIDTH: usize = 640;
+const HEIGHT: usize = 480;
+const PAGES: usize = 2;
+const GLYPH_WIDTH: usize = 8;
+const COLUMNS: usize = WIDTH / GLYPH_WIDTH;
+const ROWS: usize = HEIGHT / GLYPH_HEIGHT;
+
+pub struct HighRes<'a> {
+    display: BochsDisplay,
+    // Every page, one after another
+    framebuffer: &'a mut [Volatile<Pixel>],
+    // The page that isn't showing
+    drawing: usize,
+    active: bool,
+    // The adapter wipes video memory, which is where the VGA keeps it
+    font: [u8; FONT_BYTES],
+}
+
+impl<'a> HighRes<'a> {
+    pub fn new(phys_alloc: &mut PhysAllocator, display: BochsDisplay, vga: &Vga) -> Self {
+        let mut font = [0; FONT_BYTES];
+        font.copy_from_slice(vga.font());
+        Self {
+            framebuffer: display.framebuffer(phys_alloc, WIDTH, HEIGHT, PAGES),
+            display,
+            drawing: 1,
+            active: false,
+            font,
+        }
+    }
+
+    // Takes the screen from the VGA. Fails if the adapter can't do 640x480
+    // with two pages, and then the VGA keeps it.
+    pub fn enter(&mut self, vga: &mut Vga) -> bool {
+        if self.active {
+            return true;
+        }
+        if !self
+            .display
+            .enable(WIDTH as u16, HEIGHT as u16, PAGES as u16)
+        {
+            return false;
+        }
+        vga.set_mode(Mode::Elsewhere);
+        self.active = true;
+        self.drawing = 1;
+        true
+    }
+
+    // Back to the VGA's text mode, which needs drawing again
+    pub fn leave(&mut self, vga: &mut Vga) {
+        if !self.active {
+            return;
+        }
+        self.display.disable();
+        vga.set_mode(Mode::Text);
+        self.active = false;
+    }
+
+    fn page(&mut self) -> &mut [Volatile<Pixel>] {
+        let pixels = WIDTH * HEIGHT;
+        &mut self.framebuffer[self.drawing * pixels..(self.drawing + 1) * pixels]
+    }
+}
+
+impl Renderer for HighRes<'_> {
+    fn dimensions(&self) -> Dimensions {
+        Dimensions {
+            width: WIDTH,
+            height: HEIGHT,
+            columns: COLUMNS,
+            rows: ROWS,
+        }
+    }
+
+    fn clear(&mut self) {
+        let black = true_color(Color::Black);
+        for pixel in self.page().iter_mut() {
+            pixel.write(black);
+        }
+    }
+
+    // Spaces paint the background, the shade characters blend in that much
+    // of the foreground, and everything else paints the foreground
+    fn plot(&mut self, x: usize, y: usize, (c, color): Cell) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+        let pixel = blend(color.background(), color.foreground(), coverage(c));
+        self.page()[y * WIDTH + x].write(pixel);
+    }
+
+    fn text(&mut self, s: &str, col: usize, row: usize, color: ColorCode) -> usize {
+        let foreground = true_color(color.foreground());
+        let background = true_color(color.background());
+        let mut col = col;
+        for c in s.chars() {
+            if col >= COLUMNS || row >= ROWS {
+                break;
+            }
+            // Code page 437, like vga_buffer::plot
+            let start = c as u8 as usize * GLYPH_STRIDE;
+            let glyph: [u8; GLYPH_HEIGHT] = self.font[start..start + GLYPH_HEIGHT]
+                .try_into()
+                .unwrap_or_default();
+            let page = self.page();
+            for (y, bits) in glyph.iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    let pixel = if bits & (0x80 >> x) != 0 {
+                        foreground
+                    } else {
+                        background
+                    };
+                    page[(row * GLYPH_HEIGHT + y) * WIDTH + col * GLYPH_WIDTH + x].write(pixel);
+                }
+            }
+            col += 1;
+        }
+        col.min(COLUMNS)
+    }
+
+    // Shows the page we just drew, and draws on the other one next
+    fn present(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.display.show_page(self.drawing as u16);
+        self.drawing = (self.drawing + 1) % PAGES;
+    }
+}
+
+// The same colours as text mode, scaled up from the VGA's 6 bits per channel to 8
+fn true_color(color: Color) -> Pixel {
+    TEXT_COLORS[color as usize]
+        .iter()
+        .fold(0, |pixel, &channel| {
+            pixel << 8 | (channel as Pixel * 255 / 63)
+        })
+}
+
+// quarters out of 4 of the way from background to foreground
+fn blend(background: Color, foreground: Color, quarters: usize) -> Pixel {
+    let (background, foreground) = (true_color(background), true_color(foreground));
+    (0..3).fold(0, |pixel, channel| {
+        let shift = channel * 8;
+        let from = (background >> shift) & 0xFF;
+        let to = (foreground >> shift) & 0xFF;
+        let mixed = (from * (4 - quarters as Pixel) + to * quarters as Pixel) / 4;
+        pixel | mixed << shift
+    })
+}
@@ -0,0 +1,197 @@
+use pluggable_interrupt_os::vga_buffer::ColorCode;
+
+pub mod high_res;
+pub mod mode_13h;
+#[cfg(test)]
+pub mod recording;
+pub mod text_mode;
+
+// Everything the game draws goes through a Renderer, so it doesn't care
+// whether it ends up as text, in memory, or as pixels.
+// plot, line and fill work in the renderer's own units, which are characters
+// in text mode and pixels in a graphics mode. text always works
+// in characters, wherever the renderer puts them.
+
+// A code page 437 character and its colours. Text mode shows exactly that,
+// and a pixel renderer paints the foreground, or the background for a space,
+// or a mix of the two for the shades.
+pub type Cell = (char, ColorCode);
+
+// Code page 437's shades and blocks, see
+// https://en.wikipedia.org/wiki/Code_page_437
+// The shades go from light to solid.
+pub const LIGHT_SHADE: char = '\u{B0}';
+pub const MEDIUM_SHADE: char = '\u{B1}';
+pub const DARK_SHADE: char = '\u{B2}';
+pub const FULL_BLOCK: char = '\u{DB}';
+pub const LOWER_HALF_BLOCK: char = '\u{DC}';
+pub const LEFT_HALF_BLOCK: char = '\u{DD}';
+pub const MIDDLE_DOT: char = '\u{FA}';
+
+// Text mode cells are twice as tall as they are wide
+const CELL_ASPECT: f32 = 2.0;
+// Lines within 22.5 degrees of flat or upright are drawn as if they were
+const TAN_22_5_DEGREES: f32 = 0.414;
+
+// ASCII art for the cell at (x, y) on a line from `from` to `to`, in cells,
+// where each cell's middle is at its coordinates. Steep lines are |, in between
+// are / or \, and flat ones are ', -, or _ for whether the line crosses
+// the top, middle, or bottom of the cell, so gentle slopes step smoothly.
+// Pixel renderers don't care which character it is.
+pub fn line_glyph(from: (f32, f32), to: (f32, f32), x: usize, y: usize) -> char {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    // As it looks on screen
+    let across = dx.abs();
+    let down = dy.abs() * CELL_ASPECT;
+    if down <= across * TAN_22_5_DEGREES {
+        // Where the line is in the middle of this column, from 0 at the top
+        // of the cell to 1 at the bottom
+        let line_y = if dx == 0.0 {
+            from.1
+        } else {
+            from.1 + (x as f32 - from.0) * dy / dx
+        };
+        let within = line_y - y as f32 + 0.5;
+        if within < 1.0 / 3.0 {
+            '\''
+        } else if within < 2.0 / 3.0 {
+            '-'
+        } else {
+            '_'
+        }
+    } else if across <= down * TAN_22_5_DEGREES {
+        '|'
+    } else if (dx > 0.0) == (dy > 0.0) {
+        // Down is positive, so this goes down to the right
+        '\\'
+    } else {
+        '/'
+    }
+}
+
+// How many quarters of a pixel renderer's pixels are the foreground for c
+pub fn coverage(c: char) -> usize {
+    match c {
+        ' ' => 0,
+        LIGHT_SHADE => 1,
+        MEDIUM_SHADE => 2,
+        DARK_SHADE => 3,
+        _ => 4,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dimensions {
+    // What plot, line and fill can reach
+    pub width: usize,
+    pub height: usize,
+    // How many characters of text fit across and down
+    pub columns: usize,
+    pub rows: usize,
+}
+
+pub trait Renderer {
+    fn dimensions(&self) -> Dimensions;
+
+    // Blanks the frame being drawn, but not what's on screen
+    fn clear(&mut self);
+
+    // Anything off the edge is left out
+    fn plot(&mut self, x: usize, y: usize, cell: Cell);
+
+    // Text is cut off at the edge. Returns the column after it.
+    // This one is for renderers that plot in characters,
+    // pixel renderers draw the glyphs themselves.
+    fn text(&mut self, s: &str, col: usize, row: usize, color: ColorCode) -> usize {
+        let columns = self.dimensions().columns;
+        let mut col = col;
+        for c in s.chars() {
+            self.plot(col, row, (c, color));
+            col += 1;
+        }
+        col.min(columns)
+    }
+
+    // Shows everything drawn since the last clear
+    fn present(&mut self);
+
+    // Bresenham's line algorithm, see
+    // https://en.wikipedia.org/wiki/Bresenham%27s_line_algorithm
+    // drawn with line_glyph
+    fn line(&mut self, from: (usize, usize), to: (usize, usize), color: ColorCode) {
+        let ends = ((from.0 as f32, from.1 as f32), (to.0 as f32, to.1 as f32));
+        let (mut x1, mut y1) = (from.0 as i32, from.1 as i32);
+        let (x2, y2) = (to.0 as i32, to.1 as i32);
+        let dx = i32::abs(x2 - x1);
+        let dy = -i32::abs(y2 - y1);
+        let sx = if x1 < x2 { 1 } else { -1 };
+        let sy = if y1 < y2 { 1 } else { -1 };
+        let mut error = dx + dy;
+
+        loop {
+            let c = line_glyph(ends.0, ends.1, x1 as usize, y1 as usize);
+            self.plot(x1 as usize, y1 as usize, (c, color));
+            let e2 = 2 * error;
+            if e2 >= dy {
+                if x1 == x2 {
+                    break;
+                }
+                error += dy;
+                x1 += sx;
+            }
+            if e2 <= dx {
+                if y1 == y2 {
+                    break;
+                }
+                error += dx;
+                y1 += sy;
+            }
+        }
+    }
+
+    // A solid rectangle, with x and y at its top left
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, cell: Cell) {
+        for y in y..y + height {
+            for x in x..x + width {
+                self.plot(x, y, cell);
+            }
+        }
+    }
+
+    fn text_char(&mut self, c: char, col: usize, row: usize, color: ColorCode) -> usize {
+        self.text(c.encode_utf8(&mut [0; 4]), col, row, color)
+    }
+
+    // Pads num with spaces on the left to width characters,
+    // like vga_buffer::plot_num_right_justified. Returns the column after it.
+    fn text_num_right_justified(
+        &mut self,
+        width: usize,
+        num: isize,
+        col: usize,
+        row: usize,
+        color: ColorCode,
+    ) -> usize {
+        // Enough for any isize, and the padding
+        const MAX_LEN: usize = 40;
+        let mut digits = [b' '; MAX_LEN];
+        let mut start = MAX_LEN;
+        let mut rest = num.unsigned_abs();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        if num < 0 {
+            start -= 1;
+            digits[start] = b'-';
+        }
+        let start = start.min(MAX_LEN - width.min(MAX_LEN));
+        // Only ASCII went in, so this can't fail
+        let s = core::str::from_utf8(&digits[start..]).unwrap_or_default();
+        self.text(s, col, row, color)
+    }
+}
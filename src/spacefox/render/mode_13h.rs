@@ -0,0 +1,176 @@
+use pluggable_interrupt_os::vga_buffer::{Color, ColorCode};
+
+use super::{coverage, Cell, Dimensions, Renderer};
+use crate::{
+    isa::vga::{
+        Mode, PaletteColor, Vga, GLYPH_HEIGHT, GLYPH_STRIDE, GRAPHICS_HEIGHT, GRAPHICS_WIDTH,
+        PIXELS,
+    },
+    phys_alloc::PhysAllocator,
+};
+
+// VGA mode 13h, 320x200 pixels in 256 colours, drawn into a back buffer
+// and copied to the screen in one go by present().
+// Text is half height, 8x8, from every other row of the text mode font,
+// which fits 40x25 characters.
+
+const GLYPH_SIZE: usize = 8;
+const COLUMNS: usize = GRAPHICS_WIDTH / GLYPH_SIZE;
+const ROWS: usize = GRAPHICS_HEIGHT / GLYPH_SIZE;
+
+// The first 16 colours are text mode's, so a Color is its own palette index.
+// After them come 15 darker versions of each, from 1/16 up to 15/16 as bright,
+// at 16 + color * 15 + (sixteenths - 1).
+pub const TEXT_COLORS: [PaletteColor; 16] = [
+    [0, 0, 0],
+    [0, 0, 42],
+    [0, 42, 0],
+    [0, 42, 42],
+    [42, 0, 0],
+    [42, 0, 42],
+    [42, 21, 0],
+    [42, 42, 42],
+    [21, 21, 21],
+    [21, 21, 63],
+    [21, 63, 21],
+    [21, 63, 63],
+    [63, 21, 21],
+    [63, 21, 63],
+    [63, 63, 21],
+    [63, 63, 63],
+];
+const DIMMED_STEPS: usize = 15;
+
+// Which of four pixels in each 2x2 square are the foreground for each shade.
+// An ordered dither, see https://en.wikipedia.org/wiki/Ordered_dithering
+const BAYER_2X2: [[u8; 2]; 2] = [[0, 2], [3, 1]];
+
+pub struct Mode13h<'a> {
+    vga: Vga<'a>,
+    back: &'a mut [u8; PIXELS],
+}
+
+impl<'a> Mode13h<'a> {
+    // Has to be called while the screen is still in text mode, see Vga::new
+    pub fn new(phys_alloc: &mut PhysAllocator) -> Self {
+        Self {
+            vga: Vga::new(phys_alloc),
+            back: phys_alloc.alloc32().rw_virt,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.vga.mode() == Mode::Graphics320x200
+    }
+
+    // Switches the screen over, with our palette
+    pub fn enter(&mut self) {
+        if self.active() {
+            return;
+        }
+        self.vga.set_mode(Mode::Graphics320x200);
+        let mut palette = [[0; 3]; 256];
+        palette[..TEXT_COLORS.len()].copy_from_slice(&TEXT_COLORS);
+        for (color, full) in TEXT_COLORS.iter().enumerate() {
+            for step in 0..DIMMED_STEPS {
+                let dimmed = full.map(|channel| (channel as usize * (step + 1) / 16) as u8);
+                palette[dimmed_index(color, step + 1) as usize] = dimmed;
+            }
+        }
+        self.vga.set_palette(0, &palette);
+    }
+
+    // Back to text mode, as the bootloader left it. Whatever was on the text
+    // screen before enter() is gone, so it needs drawing again.
+    pub fn leave(&mut self) {
+        self.vga.set_mode(Mode::Text);
+    }
+
+    // For other graphics modes, which need to put text mode back afterwards
+    pub fn vga(&mut self) -> &mut Vga<'a> {
+        &mut self.vga
+    }
+}
+
+impl Renderer for Mode13h<'_> {
+    fn dimensions(&self) -> Dimensions {
+        Dimensions {
+            width: GRAPHICS_WIDTH,
+            height: GRAPHICS_HEIGHT,
+            columns: COLUMNS,
+            rows: ROWS,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.back.fill(Color::Black as u8);
+    }
+
+    // Spaces paint the background, and the shade characters mix the two colours,
+    // as a darker foreground over black or dithered over anything else.
+    // Everything else paints the foreground.
+    fn plot(&mut self, x: usize, y: usize, (c, color): Cell) {
+        if x >= GRAPHICS_WIDTH || y >= GRAPHICS_HEIGHT {
+            return;
+        }
+        let (fg, bg) = (color.foreground(), color.background());
+        let quarters = coverage(c);
+        let pixel = if quarters == 0 {
+            bg as u8
+        } else if quarters == 4 {
+            fg as u8
+        } else if bg == Color::Black {
+            dimmed_index(fg as usize, quarters * 4)
+        } else if BAYER_2X2[y % 2][x % 2] < quarters as u8 {
+            fg as u8
+        } else {
+            bg as u8
+        };
+        self.back[y * GRAPHICS_WIDTH + x] = pixel;
+    }
+
+    fn text(&mut self, s: &str, col: usize, row: usize, color: ColorCode) -> usize {
+        let mut col = col;
+        for c in s.chars() {
+            if col >= COLUMNS || row >= ROWS {
+                break;
+            }
+            // Code page 437, like vga_buffer::plot
+            let glyph = &self.vga.font()[c as u8 as usize * GLYPH_STRIDE..][..GLYPH_HEIGHT];
+            for y in 0..GLYPH_SIZE {
+                let bits = glyph[y * GLYPH_HEIGHT / GLYPH_SIZE];
+                for x in 0..GLYPH_SIZE {
+                    let pixel = if bits & (0x80 >> x) != 0 {
+                        color.foreground()
+                    } else {
+                        color.background()
+                    };
+                    self.back[(row * GLYPH_SIZE + y) * GRAPHICS_WIDTH + col * GLYPH_SIZE + x] =
+                        pixel as u8;
+                }
+            }
+            col += 1;
+        }
+        col.min(COLUMNS)
+    }
+
+    fn present(&mut self) {
+        if !self.active() {
+            return;
+        }
+        for (pixel, &value) in self.vga.pixels().iter_mut().zip(self.back.iter()) {
+            pixel.write(value);
+        }
+    }
+}
+
+// Where color at sixteenths of its brightness is in the palette
+fn dimmed_index(color: usize, sixteenths: usize) -> u8 {
+    if sixteenths >= 16 {
+        color as u8
+    } else if sixteenths == 0 {
+        Color::Black as u8
+    } else {
+        (TEXT_COLORS.len() + color * DIMMED_STEPS + sixteenths - 1) as u8
+    }
+}
@@ -0,0 +1,117 @@
+use pluggable_interrupt_os::vga_buffer::{BUFFER_HEIGHT, BUFFER_WIDTH};
+
+use super::{text_mode::blank, Cell, Dimensions, Renderer};
+
+// Keeps each presented frame in memory instead of showing it, the same size
+// as text mode, so what the game draws can be checked without a screen.
+// Only built for cargo test, as nothing in the kernel draws to one.
+
+const CELLS: usize = BUFFER_WIDTH * BUFFER_HEIGHT;
+
+pub struct Recording {
+    drawing: [Cell; CELLS],
+    presented: [Cell; CELLS],
+    presents: usize,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self {
+            drawing: [blank(); CELLS],
+            presented: [blank(); CELLS],
+            presents: 0,
+        }
+    }
+
+    // What was there at the last present()
+    pub fn cell(&self, col: usize, row: usize) -> Cell {
+        self.presented[row * BUFFER_WIDTH + col]
+    }
+
+    // The characters across a row at the last present(), without their colours
+    pub fn row(&self, row: usize) -> impl Iterator<Item = char> + '_ {
+        self.presented[row * BUFFER_WIDTH..(row + 1) * BUFFER_WIDTH]
+            .iter()
+            .map(|&(c, _)| c)
+    }
+
+    // How many frames have been presented
+    pub fn presents(&self) -> usize {
+        self.presents
+    }
+}
+
+impl Renderer for Recording {
+    fn dimensions(&self) -> Dimensions {
+        Dimensions {
+            width: BUFFER_WIDTH,
+            height: BUFFER_HEIGHT,
+            columns: BUFFER_WIDTH,
+            rows: BUFFER_HEIGHT,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.drawing = [blank(); CELLS];
+    }
+
+    fn plot(&mut self, x: usize, y: usize, cell: Cell) {
+        if x < BUFFER_WIDTH && y < BUFFER_HEIGHT {
+            self.drawing[y * BUFFER_WIDTH + x] = cell;
+        }
+    }
+
+    fn present(&mut self) {
+        self.presented = self.drawing;
+        self.presents += 1;
+    }
+}
+
+mod tests {
+    use pluggable_interrupt_os::vga_buffer::{Color, ColorCode};
+
+    use super::{super::Renderer, Recording};
+
+    fn yellow() -> ColorCode {
+        ColorCode::new(Color::Yellow, Color::Black)
+    }
+
+    #[test_case]
+    fn only_presented_frames_are_recorded() {
+        let mut screen = Recording::new();
+        screen.text("HELLO", 2, 1, yellow());
+        assert_eq!(screen.presents(), 0);
+        assert_eq!(screen.cell(2, 1).0, ' ');
+
+        screen.present();
+        assert_eq!(screen.presents(), 1);
+        assert!(screen.row(1).skip(2).take(5).eq("HELLO".chars()));
+        assert_eq!(screen.cell(2, 1), ('H', yellow()));
+
+        // Clearing only touches the frame being drawn
+        screen.clear();
+        assert_eq!(screen.cell(2, 1).0, 'H');
+        screen.present();
+        assert_eq!(screen.cell(2, 1).0, ' ');
+    }
+
+    #[test_case]
+    fn text_is_cut_off_at_the_edge() {
+        let mut screen = Recording::new();
+        let columns = screen.dimensions().columns;
+        assert_eq!(screen.text("ABCD", columns - 2, 0, yellow()), columns);
+        screen.present();
+        assert!(screen.row(0).skip(columns - 2).eq("AB".chars()));
+    }
+
+    #[test_case]
+    fn lines_fill_every_cell_between_their_ends() {
+        let mut screen = Recording::new();
+        screen.line((0, 3), (9, 3), yellow());
+        screen.line((20, 0), (20, 4), yellow());
+        screen.present();
+        assert!(screen.row(3).take(10).all(|c| c == '-'));
+        assert_eq!(screen.row(3).nth(10), Some(' '));
+        assert!((0..5).all(|row| screen.cell(20, row).0 == '|'));
+    }
+}
@@ -0,0 +1,67 @@
+use pluggable_interrupt_os::vga_buffer::{plot, Color, ColorCode, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+use super::{Cell, Dimensions, Renderer};
+
+// The VGA's 80x25 text mode, drawn off screen every frame and then written out
+// in one go. Nothing has to be erased cell by cell, so nothing flickers between
+// the old frame going and the new one arriving, and overlays can't get holes
+// punched in them. Only the cells that changed touch VGA memory.
+
+const CELLS: usize = BUFFER_WIDTH * BUFFER_HEIGHT;
+
+pub struct TextMode {
+    cells: [Cell; CELLS],
+    // What the last present() wrote, or None where we can't be sure
+    shown: [Option<Cell>; CELLS],
+}
+
+impl TextMode {
+    pub fn new() -> Self {
+        Self {
+            cells: [blank(); CELLS],
+            shown: [None; CELLS],
+        }
+    }
+
+    // For when something else drew on the screen, like println! on the menu,
+    // so the next present() writes every cell
+    pub fn forget_screen(&mut self) {
+        self.shown = [None; CELLS];
+    }
+}
+
+impl Renderer for TextMode {
+    fn dimensions(&self) -> Dimensions {
+        Dimensions {
+            width: BUFFER_WIDTH,
+            height: BUFFER_HEIGHT,
+            columns: BUFFER_WIDTH,
+            rows: BUFFER_HEIGHT,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.cells = [blank(); CELLS];
+    }
+
+    fn plot(&mut self, x: usize, y: usize, cell: Cell) {
+        if x < BUFFER_WIDTH && y < BUFFER_HEIGHT {
+            self.cells[y * BUFFER_WIDTH + x] = cell;
+        }
+    }
+
+    // Writes out every cell that's different from what's on screen
+    fn present(&mut self) {
+        for (i, (&cell, shown)) in self.cells.iter().zip(self.shown.iter_mut()).enumerate() {
+            if *shown != Some(cell) {
+                let (c, color) = cell;
+                plot(c, i % BUFFER_WIDTH, i / BUFFER_WIDTH, color);
+                *shown = Some(cell);
+            }
+        }
+    }
+}
+
+pub fn blank() -> Cell {
+    (' ', ColorCode::new(Color::Black, Color::Black))
+}
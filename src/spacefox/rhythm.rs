@@ -0,0 +1,107 @@
+use crate::audio::{Ending, SAMPLE_RATE};
+
+// Rhythm mode: instead of a new tower whenever the last one goes past,
+// each tower is timed to reach the ship right on a beat of the music.
+// build.rs finds the beats in each song ahead of time (see beat_map_source),
+// and we line them up with what's actually coming out of the speakers,
+// see Mixer::music_position.
+
+// One tower's worth of beat
+#[derive(Debug, Clone, Copy)]
+pub struct Beat {
+    // Frames into the song, at SAMPLE_RATE
+    pub frame: u32,
+    // -1 left, 0 middle, 1 right
+    pub lane: i8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BeatMap {
+    pub bpm: u16,
+    // How long the song is, at SAMPLE_RATE
+    pub frames: u32,
+    // In order
+    pub beats: &'static [Beat],
+}
+
+// Towers never come closer together than this, or two beats,
+// whichever is longer, so there's always time to dodge
+const MIN_GAP_MS: u32 = 1200;
+const MIN_GAP_BEATS: u32 = 2;
+
+pub struct Rhythm {
+    map: &'static BeatMap,
+    // Where the song goes back to at the end, None if it stops
+    loop_from: Option<usize>,
+    min_gap: usize,
+    // The beat the current tower arrives on, as a position
+    // that counts on through loops like Playlist::position
+    target: Option<(usize, i8)>,
+}
+
+impl Rhythm {
+    // ending has to be the same as the song's cue
+    pub fn new(map: &'static BeatMap, ending: Ending) -> Self {
+        let beat_frames = 60 * SAMPLE_RATE / map.bpm.max(1) as u32;
+        let min_gap = (MIN_GAP_MS * SAMPLE_RATE / 1000).max(MIN_GAP_BEATS * beat_frames);
+        Self {
+            map,
+            loop_from: match ending {
+                Ending::LoopFrom(frame) => Some(frame.min(map.frames as usize)),
+                Ending::Stop => None,
+            },
+            min_gap: min_gap as usize,
+            target: None,
+        }
+    }
+
+    // How many seconds until the current tower reaches the ship
+    // (negative once it's gone past), and which lane it's in.
+    // The first call after next_tower() picks the beat for the next one.
+    // None once the song has stopped, or if it has no beats at all.
+    pub fn tower(&mut self, position: usize) -> Option<(f32, i8)> {
+        if self.target.is_none() {
+            self.target = self.beat_after(position + self.min_gap);
+        }
+        let (frame, lane) = self.target?;
+        let seconds = (frame as f32 - position as f32) / SAMPLE_RATE as f32;
+        Some((seconds, lane))
+    }
+
+    pub fn next_tower(&mut self) {
+        self.target = None;
+    }
+
+    // The first beat at or after position, going around loops as needed
+    fn beat_after(&self, position: usize) -> Option<(usize, i8)> {
+        let frames = self.map.frames as usize;
+        // This time through the song, frame f of the song is position start + f,
+        // for the frames from 'from' on
+        let (mut start, mut from) = if position < frames {
+            (0, 0)
+        } else {
+            let loop_from = self.loop_from?;
+            let loop_len = frames.checked_sub(loop_from).filter(|&len| len > 0)?;
+            let times_looped = (position - frames) / loop_len;
+            (frames + times_looped * loop_len - loop_from, loop_from)
+        };
+
+        // At most into the next time through, unless there are no beats
+        for _ in 0..2 {
+            let next = self
+                .map
+                .beats
+                .iter()
+                .filter(|beat| beat.frame as usize >= from)
+                .map(|beat| (start + beat.frame as usize, beat.lane))
+                .find(|&(frame, _)| frame >= position);
+            if next.is_some() {
+                return next;
+            }
+            let loop_from = self.loop_from?;
+            start += frames - loop_from;
+            from = loop_from;
+        }
+        None
+    }
+}
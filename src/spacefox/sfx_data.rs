@@ -0,0 +1,81 @@
+use crate::audio::synth::{Sfx, Waveform};
+
+// All our sound effects are synthesized when they play, see audio::synth.
+// https://sfxr.me is handy for finding numbers that sound right,
+// though its parameters don't map one to one onto these.
+
+// Low rumbling noise that sinks as it fades out
+pub const EXPLOSION: Sfx = Sfx {
+    waveform: Waveform::Noise,
+    start_hz: 6000,
+    end_hz: 400,
+    duty: 128,
+    attack_ms: 2,
+    decay_ms: 120,
+    sustain_ms: 80,
+    release_ms: 500,
+    sustain_level: 150,
+    volume: 255,
+    lowpass: 60,
+};
+
+// Short rising chirp when a tower goes past
+pub const SCORE_BLIP: Sfx = Sfx {
+    waveform: Waveform::Square,
+    start_hz: 880,
+    end_hz: 1760,
+    duty: 64,
+    attack_ms: 1,
+    decay_ms: 20,
+    sustain_ms: 30,
+    release_ms: 40,
+    sustain_level: 140,
+    volume: 110,
+    lowpass: 200,
+};
+
+// Buzzy swoop for scraping past a tower
+pub const NEAR_MISS: Sfx = Sfx {
+    waveform: Waveform::Saw,
+    start_hz: 1400,
+    end_hz: 350,
+    duty: 128,
+    attack_ms: 5,
+    decay_ms: 60,
+    sustain_ms: 60,
+    release_ms: 120,
+    sustain_level: 160,
+    volume: 150,
+    lowpass: 120,
+};
+
+// Tick for menu key presses
+pub const MENU_CLICK: Sfx = Sfx {
+    waveform: Waveform::Triangle,
+    start_hz: 1200,
+    end_hz: 600,
+    duty: 128,
+    attack_ms: 0,
+    decay_ms: 15,
+    sustain_ms: 0,
+    release_ms: 15,
+    sustain_level: 80,
+    volume: 180,
+    lowpass: 255,
+};
+
+// Rushing air as a tower goes past, played from the tower's position
+// so it pans across and drops in pitch, see place_tower_whoosh in spacefox/mod.rs
+pub const TOWER_WHOOSH: Sfx = Sfx {
+    waveform: Waveform::Noise,
+    start_hz: 9000,
+    end_hz: 5000,
+    duty: 128,
+    attack_ms: 150,
+    decay_ms: 200,
+    sustain_ms: 400,
+    release_ms: 400,
+    sustain_level: 200,
+    volume: 200,
+    lowpass: 40,
+};
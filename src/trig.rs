@@ -0,0 +1,37 @@
+// core has no trig or sqrt without std, so these are shared by the game's 3D maths
+// and the audio code.
+
+// Wraps angle into -PI..=PI, folds that into -PI/2..=PI/2,
+// and uses Taylor series, which are good to about 1e-4 there.
+pub fn sin_cos(angle: f32) -> (f32, f32) {
+    use core::f32::consts::{FRAC_PI_2, PI, TAU};
+
+    // Rounding to the nearest whole turn, as casting truncates towards 0
+    let turns = angle / TAU;
+    let turns = (turns + if turns < 0.0 { -0.5 } else { 0.5 }) as i32 as f32;
+    let x = angle - turns * TAU;
+    let (x, cos_sign) = if x > FRAC_PI_2 {
+        (PI - x, -1.0)
+    } else if x < -FRAC_PI_2 {
+        (-PI - x, -1.0)
+    } else {
+        (x, 1.0)
+    };
+    let x2 = x * x;
+    let sin = x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0)));
+    let cos = 1.0 - x2 / 2.0 * (1.0 - x2 / 12.0 * (1.0 - x2 / 30.0 * (1.0 - x2 / 56.0)));
+    (sin, cos * cos_sign)
+}
+
+// Halving the float's exponent gets close, and a few steps
+// of Newton's method from there are plenty for lighting and panning
+pub fn sqrt(x: f32) -> f32 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let mut guess = f32::from_bits((x.to_bits() >> 1) + (127 << 22));
+    for _ in 0..3 {
+        guess = (guess + x / guess) / 2.0;
+    }
+    guess
+}